@@ -19,6 +19,21 @@
 
 use rstox::core::*;
 
+use std::io::prelude::*;
+use std::fs::File;
+
+use rand::{self, Rng};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::Json;
+
+
+/*
+    According to Tox client guidelines, at least 4 random nodes should be
+    used for bootstrapping.
+*/
+pub const MIN_NODES: usize = 4;
+
 /*
     Bootstrap nodes section, should be used only when there's no available file
     with bootstrap nodes.
@@ -34,7 +49,6 @@ use rstox::core::*;
 
     [1] https://github.com/irungentoo/Tox_Client_Guidelines/blob/master/Required/Bootstrapping.md
 */
-// TODO: use those only when there's no list with bootstrap nodes present
 struct BootNode<'a> {
     name: &'a str,
     ips: &'a [&'a str],
@@ -72,17 +86,241 @@ const BOOTSTRAP_NODES: &'static [BootNode<'static>] = &[
     },
 ];
 
+/**
+    Bootstrap node loaded from a file.
+*/
+#[derive(Debug)]
+pub struct Node {
+    /**
+        Name of node, or its maintainer, if known. Used only for printing.
+    */
+    pub name: String,
+    /**
+        IPv4 and/or IPv6 addresses, or hostnames of the node.
+    */
+    pub addresses: Vec<String>,
+    pub port: u16,
+    pub key: PublicKey,
+}
+
+
+/*
+    Make `Node` out of its parts, checking that all of them are valid.
+
+    In case where something is not right, return an error describing what
+    exactly is wrong.
+*/
+fn make_node(name: &str, addresses: Vec<String>, port: i64, key: &str)
+    -> Result<Node, String>
+{
+    if addresses.is_empty() {
+        return Err("missing address".to_string());
+    }
+    if port <= 0 || port > 65535 {
+        return Err(format!("invalid port {}", port));
+    }
+    let key = match key.parse() {
+        Ok(k) => k,
+        Err(_) => return Err(format!("invalid public key {:?}", key)),
+    };
+
+    Ok(Node {
+        name: name.to_string(),
+        addresses: addresses,
+        port: port as u16,
+        key: key,
+    })
+}
+
+
+/*
+    Parse plaintext list of nodes.
+
+    Each line should contain address, port and public key of a node,
+    separated by whitespace, optionally followed by a name of a node.
+    Empty lines and lines starting with `#` are ignored.
+
+    Lines with the same key and port are addresses of the same node, e.g.
+    its IPv4 and IPv6 addresses, and are merged into one `Node`.
+
+    ```text
+    # address       port  key                         name
+    144.76.60.215   33445 04119E835DF3E78BACF0F842... sonOfRa
+    ```
+
+    Lines that can't be parsed are skipped with a warning.
+*/
+fn parse_txt(file: &str, content: &str) -> Vec<Node> {
+    let mut nodes = vec![];
+    for (num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 3 {
+            warn!("{}:{}: expected `address port key [name]`, skipping line",
+                  file, num + 1);
+            continue;
+        }
+
+        let port = match parts[1].parse() {
+            Ok(p) => p,
+            Err(_) => {
+                warn!("{}:{}: invalid port {:?}, skipping line", file,
+                      num + 1, parts[1]);
+                continue;
+            },
+        };
+        let name = parts[3..].join(" ");
+        let node = match make_node(&name, vec![parts[0].to_string()], port,
+                                   parts[2]) {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("{}:{}: {}, skipping line", file, num + 1, e);
+                continue;
+            },
+        };
+
+        let (port, key) = (node.port, format!("{}", node.key));
+        match nodes.iter_mut().find(|n: &&mut Node|
+                n.port == port && format!("{}", n.key) == key) {
+            Some(n) => n.addresses.extend(node.addresses),
+            None => nodes.push(node),
+        }
+    }
+    nodes
+}
+
+
+/*
+    Parse list of nodes in JSON format used by the list of public nodes,
+    i.e. an object with `nodes` array, where each node has `ipv4`, `ipv6`,
+    `port`, `public_key` and `maintainer` fields.
+
+    Node with both IPv4 and IPv6 address results in one `Node` with two
+    addresses. Address `-` means that node doesn't have address of given
+    type.
+
+    Nodes with missing or invalid fields are skipped with a warning.
+*/
+fn parse_json(file: &str, content: &str) -> Result<Vec<Node>, String> {
+    let json = match Json::from_str(content) {
+        Ok(j) => j,
+        Err(e) => return Err(format!("invalid JSON: {}", e)),
+    };
+
+    let list = match json.find("nodes").and_then(|n| n.as_array()) {
+        Some(l) => l,
+        None => return Err("no `nodes` array".to_string()),
+    };
+
+    let mut nodes = vec![];
+    for (num, node) in list.iter().enumerate() {
+        let name = node.find("maintainer").and_then(|m| m.as_string())
+            .unwrap_or("");
+        let port = node.find("port").and_then(|p| p.as_i64()).unwrap_or(0);
+        let key = node.find("public_key").and_then(|k| k.as_string())
+            .unwrap_or("");
+
+        let addresses = ["ipv4", "ipv6"].iter()
+            .filter_map(|f| node.find(f).and_then(|a| a.as_string()))
+            .filter(|a| *a != "" && *a != "-")
+            .map(|a| a.to_string())
+            .collect();
+
+        match make_node(name, addresses, port, key) {
+            Ok(n) => nodes.push(n),
+            Err(e) => warn!("{}: node #{}: {}, skipping it", file, num, e),
+        }
+    }
+    Ok(nodes)
+}
+
+
+/**
+    Load bootstrap nodes from a file.
+
+    File is treated as JSON if it's name ends with `.json`, or if its
+    content starts with `{`, otherwise it's treated as a plaintext list.
+
+    Returns an error if file can't be read or parsed at all. Invalid entries
+    are only skipped, thus returned list can be empty.
+*/
+pub fn load_nodes(file: &str) -> Result<Vec<Node>, String> {
+    let mut content = String::new();
+    match File::open(file) {
+        Ok(mut f) => {
+            if let Err(e) = f.read_to_string(&mut content) {
+                return Err(format!("{}", e));
+            }
+        },
+        Err(e) => return Err(format!("{}", e)),
+    }
+
+    if file.ends_with(".json") || content.trim_left().starts_with("{") {
+        parse_json(file, &content)
+    } else {
+        Ok(parse_txt(file, &content))
+    }
+}
+
+
+/**
+    Bootstrap from nodes listed in a file.
+
+    Randomly picks `amount` nodes from a list, but no less than `MIN_NODES`,
+    or all of them if there aren't enough, and bootstraps from every address
+    of each picked node.
+
+    Returns `false` if file couldn't be used, i.e. when it can't be loaded,
+    contains no valid nodes, or bootstrapping from all of them failed – in
+    that case one should fall back on `bootstrap_hardcoded()`.
+*/
+pub fn bootstrap_from_file(tox: &mut Tox, file: &str, amount: usize) -> bool {
+    let mut nodes = match load_nodes(file) {
+        Ok(n) => n,
+        Err(e) => {
+            warn!("Can't load bootstrap nodes from `{}`: {}", file, e);
+            return false;
+        },
+    };
+
+    if nodes.is_empty() {
+        warn!("No valid bootstrap nodes in `{}`", file);
+        return false;
+    }
+
+    rand::thread_rng().shuffle(&mut nodes);
+    let amount = if amount < MIN_NODES { MIN_NODES } else { amount };
+    nodes.truncate(amount);
+
+    let mut bootstrapped = false;
+    for node in &nodes {
+        for address in &node.addresses {
+            info!("Bootstrapping from {}: [{}]:{}, key: {}",
+                  node.name, address, node.port, node.key);
+            match tox.bootstrap(address, node.port, node.key.clone()) {
+                Ok(_) => bootstrapped = true,
+                Err(e) => warn!("Failed to bootstrap from [{}]:{}: {:?}",
+                                address, node.port, e),
+            }
+        }
+    }
+    bootstrapped
+}
+
+
 /*
-    Function should be skipped when there is provided file with bootstrap
+    Function should be used only when there is no usable file with bootstrap
     nodes. Should be called after Tox instance will be initialized.
 */
-// TODO: ↓ check whether bootstrap nodes were loaded from a file, and if that
-// was the case, then skip this function
 pub fn bootstrap_hardcoded(tox: &mut Tox) {
     for node in BOOTSTRAP_NODES {
         let key = node.key.parse().unwrap();
         for ip in node.ips {
-            println!("Bootstrapping from {}: [{}]:{}, key: {}", node.name, ip, node.port, node.key);
+            info!("Bootstrapping from {}: [{}]:{}, key: {}", node.name, ip, node.port, node.key);
             tox.bootstrap(ip, node.port, key).unwrap();
         }
     }
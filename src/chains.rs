@@ -0,0 +1,251 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Chains of groupchats.
+
+    Every groupchat can have its own chain, so that Lee would talk in it
+    like people in that groupchat do, rather than like everyone everywhere.
+    Messages from groupchat are learned by both its chain and the global
+    one, and until chain of a groupchat has learned enough, the global one
+    is used instead.
+
+    Group numbers change whenever Lee rejoins, thus chains are identified
+    by a key derived from title of groupchat. Every chain is saved in its
+    own file, named after the key, along with number of messages that it
+    has learned.
+*/
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+use markov::Chain;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::{self, Json};
+
+use corpus::{Entry, Source};
+use for_files;
+use hashes::hash_message;
+
+
+/**
+    Key identifying chain of a groupchat with given title.
+*/
+pub fn group_key(title: &str) -> String {
+    format!("{:016x}", hash_message(title))
+}
+
+
+pub struct GroupChain {
+    pub chain: Chain<String>,
+
+    /**
+        Number of messages that chain has learned.
+    */
+    pub learned: u64,
+
+    /**
+        Whether chain has changed since it was saved.
+    */
+    dirty: bool,
+}
+
+
+pub struct GroupChains {
+    dir: String,
+    order: usize,
+    backups: usize,
+    chains: HashMap<String, GroupChain>,
+}
+
+
+impl GroupChains {
+    pub fn new(dir: &str, order: usize, backups: usize) -> GroupChains {
+        GroupChains {
+            dir: dir.to_string(),
+            order: order,
+            backups: backups,
+            chains: HashMap::new(),
+        }
+    }
+
+    fn file(&self, key: &str) -> String {
+        Path::new(&self.dir).join(format!("{}.json", key))
+            .to_string_lossy().into_owned()
+    }
+
+    /**
+        Files of chains that are loaded, or were rebuilt.
+    */
+    pub fn files(&self) -> Vec<String> {
+        self.chains.keys().map(|k| self.file(k)).collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&GroupChain> {
+        self.chains.get(key)
+    }
+
+    /**
+        Get chain with given key, loading it if it wasn't loaded yet, or
+        making a new one if it wasn't saved before.
+    */
+    pub fn get_mut(&mut self, key: &str) -> &mut GroupChain {
+        if !self.chains.contains_key(key) {
+            let file = self.file(key);
+            let chain = match for_files::load_with_backups(&file, self.backups,
+                                                           load_group_chain) {
+                Ok(c) => {
+                    info!("Loaded chain of groupchat from `{}`, {} messages.",
+                          file, c.learned);
+                    c
+                },
+                Err(e) => {
+                    if fs::metadata(&file).is_ok() {
+                        warn!("Error loading `{}`: {}", file, e);
+                    }
+                    GroupChain {
+                        chain: Chain::for_strings().order(self.order),
+                        learned: 0,
+                        dirty: false,
+                    }
+                },
+            };
+            self.chains.insert(key.to_string(), chain);
+        }
+        self.chains.get_mut(key).unwrap()
+    }
+
+    /**
+        Feed chain with given key with a message.
+    */
+    pub fn feed(&mut self, key: &str, message: &str) {
+        let group = self.get_mut(key);
+        group.chain.feed_str(message);
+        group.learned += 1;
+        group.dirty = true;
+    }
+
+    /**
+        Replace all chains with ones of given `order`, fed with `entries`
+        that came from groupchats with titles.
+
+        Chains that were saved before, but have no entries, are replaced
+        with empty ones.
+    */
+    pub fn rebuild(&mut self, entries: &[Entry], order: usize) {
+        self.order = order;
+        self.chains.clear();
+
+        if let Ok(dir) = fs::read_dir(&self.dir) {
+            for file in dir.filter_map(|f| f.ok()).map(|f| f.path()) {
+                let is_chain = file.extension().map(|e| e == "json")
+                                   .unwrap_or(false);
+                if let (true, Some(key)) = (is_chain, file.file_stem()) {
+                    self.chains.insert(key.to_string_lossy().into_owned(),
+                                       GroupChain {
+                                           chain: Chain::for_strings().order(order),
+                                           learned: 0,
+                                           dirty: true,
+                                       });
+                }
+            }
+        }
+
+        for entry in entries {
+            if let Source::Group(_, Some(ref title)) = entry.source {
+                let key = group_key(title);
+                if !self.chains.contains_key(&key) {
+                    self.chains.insert(key.clone(), GroupChain {
+                        chain: Chain::for_strings().order(order),
+                        learned: 0,
+                        dirty: true,
+                    });
+                }
+                self.feed(&key, &entry.text);
+            }
+        }
+        info!("Rebuilt {} chains of groupchats.", self.chains.len());
+    }
+
+    /**
+        Save chains that have changed. Chains that failed to be saved are
+        left marked as changed, and their errors are returned.
+    */
+    pub fn save(&mut self) -> Result<(), String> {
+        let keys: Vec<String> = self.chains.iter()
+            .filter(|&(_, c)| c.dirty).map(|(k, _)| k.clone()).collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            return Err(format!("can't create `{}`: {}", self.dir, e));
+        }
+
+        let backups = self.backups;
+        let mut errors = vec![];
+        for key in keys {
+            let file = self.file(&key);
+            let group = self.chains.get_mut(&key).unwrap();
+            let saved = json::encode(&group.chain)
+                .map_err(|e| format!("{}", e))
+                .and_then(|chain| {
+                    let data = format!("{{\"learned\":{},\"chain\":{}}}",
+                                       group.learned, chain);
+                    for_files::write_save(&file, data.into_bytes(), backups)
+                });
+            match saved {
+                Ok(()) => group.dirty = false,
+                Err(e) => errors.push(format!("`{}`: {}", file, e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+
+/*
+    Load chain of a groupchat, saved by `GroupChains::save()`.
+*/
+fn load_group_chain(f: &str) -> Result<GroupChain, String> {
+    let mut content = String::new();
+    try!(File::open(f).and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("{}", e)));
+
+    let saved = try!(Json::from_str(&content).map_err(|e| format!("{}", e)));
+    let learned = saved.find("learned").and_then(|l| l.as_u64()).unwrap_or(0);
+    let chain = match saved.find("chain") {
+        Some(c) => try!(json::decode(&c.to_string())
+                            .map_err(|e| format!("{}", e))),
+        None => return Err("no chain in file".to_string()),
+    };
+
+    Ok(GroupChain {
+        chain: chain,
+        learned: learned,
+        dirty: false,
+    })
+}
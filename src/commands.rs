@@ -0,0 +1,485 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Registry of commands that Lee understands.
+
+    Every command is registered with its name, aliases, arguments that it
+    takes, place where it can be used and a handler. Registry then takes
+    care of recognizing commands in messages, parsing their arguments and
+    describing them for `.help`.
+
+    Registry is generic over `B`, which is whatever handlers operate on –
+    in Lee's case it's the `Bot`.
+*/
+
+use regex::Regex;
+
+use rstox::core::PublicKey;
+
+use transport::Chat;
+
+
+/*
+    Commands that take arguments, but can still be used without a prefix in
+    friend chats – as long as they're used without arguments. Status
+    message has been telling people to send `invite` since forever.
+*/
+const BARE: &'static [&'static str] = &["invite"];
+
+
+/**
+    Where command can be used.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Group,
+    Friend,
+    Both,
+}
+
+impl Scope {
+    pub fn allows(&self, chat: Chat) -> bool {
+        match (*self, chat) {
+            (Scope::Both, _) => true,
+            (Scope::Group, Chat::Group(_)) => true,
+            (Scope::Friend, Chat::Friend(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+
+/**
+    Argument that command takes.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    /**
+        Single word, that has to be supplied.
+    */
+    Word(&'static str),
+
+    /**
+        Single word, that can be omitted.
+    */
+    OptWord(&'static str),
+
+    /**
+        All of the remaining text, that has to be supplied. Should be the
+        last argument.
+    */
+    Text(&'static str),
+
+    /**
+        All of the remaining text, that can be omitted. Should be the last
+        argument.
+    */
+    OptText(&'static str),
+}
+
+
+/**
+    Invocation of a command.
+*/
+#[derive(Clone, Debug)]
+pub struct Call {
+    /**
+        Name of command, without prefix, even if alias was used.
+    */
+    pub name: &'static str,
+
+    /**
+        Chat from which command came, and to which reply should go.
+    */
+    pub chat: Chat,
+
+    /**
+        Public key of peer that used command.
+    */
+    pub pk: PublicKey,
+
+    /**
+        Parsed arguments, in order in which they are declared. Omitted
+        `OptWord` and `OptText` arguments are not there.
+    */
+    pub args: Vec<String>,
+}
+
+
+pub type Handler<B> = fn(&mut B, &Call);
+
+
+pub struct Command<B> {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static [Arg],
+    pub scope: Scope,
+
+    /**
+        Short description of what command does, for `.help`.
+    */
+    pub description: &'static str,
+
+    /**
+        Examples of use, without prefix, for `.help <command>`.
+    */
+    pub examples: &'static [&'static str],
+
+    pub handler: Handler<B>,
+}
+
+
+impl<B> Command<B> {
+    /**
+        Check whether command has given name or alias.
+    */
+    pub fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| *a == name)
+    }
+}
+
+
+/**
+    Result of recognizing a command in a message.
+*/
+pub enum Parsed<B> {
+    /**
+        Message isn't a command.
+    */
+    NotCommand,
+
+    /**
+        Command with valid arguments, ready to be handled.
+    */
+    Command(Handler<B>, Call),
+
+    /**
+        Command, but used in a wrong place, or with invalid arguments.
+        Contains name of command, and message that should be sent back.
+    */
+    Invalid(&'static str, String),
+}
+
+
+pub struct Registry<B> {
+    /**
+        Prefix that commands have to start with, e.g. `.`.
+    */
+    pub prefix: String,
+
+    commands: Vec<Command<B>>,
+
+    /**
+        Matches nick prefixes added by bridges to relayed messages, e.g.
+        `[nick] ` or `<nick> `.
+    */
+    bridge: Regex,
+}
+
+
+impl<B> Registry<B> {
+    pub fn new(prefix: &str) -> Registry<B> {
+        Registry {
+            prefix: prefix.to_string(),
+            commands: vec![],
+            bridge: Regex::new(r"^(\[[^\]]*\]|<[^>]*>)\s?").unwrap(),
+        }
+    }
+
+    pub fn register(&mut self, command: Command<B>) {
+        self.commands.push(command);
+    }
+
+    /**
+        All registered commands, in order of registration.
+    */
+    pub fn commands(&self) -> &[Command<B>] {
+        &self.commands
+    }
+
+    /**
+        Find command by its name or alias, with or without prefix.
+    */
+    pub fn find(&self, name: &str) -> Option<&Command<B>> {
+        let name = if name.starts_with(&*self.prefix) {
+            &name[self.prefix.len()..]
+        } else {
+            name
+        };
+        self.find_exact(name)
+    }
+
+    fn find_exact(&self, name: &str) -> Option<&Command<B>> {
+        self.commands.iter().find(|c| c.is_called(name))
+    }
+
+    /**
+        Usage of a command, e.g. `.help [command]`.
+    */
+    pub fn usage(&self, command: &Command<B>) -> String {
+        let mut usage = format!("{}{}", self.prefix, command.name);
+        for arg in command.args {
+            match *arg {
+                Arg::Word(a) | Arg::Text(a) => usage.push_str(&format!(" <{}>", a)),
+                Arg::OptWord(a) | Arg::OptText(a) => usage.push_str(&format!(" [{}]", a)),
+            }
+        }
+        usage
+    }
+
+    /**
+        Recognize command in a message from `chat`, sent by peer with `pk`.
+
+        Nick prefix added by a bridge is skipped. In friend chats, where
+        every message is directed at Lee, command prefix can be omitted –
+        but only for a message that is just a name of a command that takes
+        no arguments, or of one of `BARE` commands, so that e.g. "help me"
+        or "stop" would be just a normal message.
+
+        Unknown commands are treated as normal messages.
+    */
+    pub fn parse(&self, msg: &str, chat: Chat, pk: &PublicKey) -> Parsed<B> {
+        let msg = match self.bridge.find(msg) {
+            Some((_, end)) => &msg[end..],
+            None => msg,
+        };
+
+        let prefixed = msg.starts_with(&*self.prefix);
+        let msg = if prefixed {
+            &msg[self.prefix.len()..]
+        } else if let Chat::Friend(_) = chat {
+            msg
+        } else {
+            return Parsed::NotCommand;
+        };
+
+        let (name, rest) = split_word(msg);
+        let command = match self.find_exact(name) {
+            Some(c) => c,
+            None => return Parsed::NotCommand,
+        };
+
+        if !prefixed && (!rest.trim().is_empty()
+                         || !(command.args.is_empty() || BARE.contains(&command.name))
+                         || !command.scope.allows(chat)) {
+            return Parsed::NotCommand;
+        }
+
+        let args = parse_args(command.args, rest);
+
+        if !command.scope.allows(chat) {
+            let place = match chat {
+                Chat::Group(_) => "groupchats",
+                Chat::Friend(_) => "friend chats",
+            };
+            return Parsed::Invalid(command.name,
+                format!("`{}{}` can't be used in {}.",
+                        self.prefix, command.name, place));
+        }
+
+        match args {
+            Some(args) => Parsed::Command(command.handler, Call {
+                name: command.name,
+                chat: chat,
+                pk: pk.clone(),
+                args: args,
+            }),
+            None => Parsed::Invalid(command.name,
+                format!("Usage: {}", self.usage(command))),
+        }
+    }
+}
+
+
+/*
+    Split first word off the string, returning it and the rest of the
+    string.
+*/
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_left();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, ""),
+    }
+}
+
+
+/*
+    Parse arguments according to schema. Returns `None` if required
+    argument is missing, or if there is something left over.
+*/
+fn parse_args(schema: &[Arg], mut rest: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    for arg in schema {
+        match *arg {
+            Arg::Word(_) | Arg::OptWord(_) => {
+                let (word, r) = split_word(rest);
+                rest = r;
+                if !word.is_empty() {
+                    args.push(word.to_string());
+                } else if let Arg::Word(_) = *arg {
+                    return None;
+                }
+            },
+            Arg::Text(_) => {
+                let text = rest.trim();
+                rest = "";
+                if text.is_empty() {
+                    return None;
+                }
+                args.push(text.to_string());
+            },
+            Arg::OptText(_) => {
+                let text = rest.trim();
+                rest = "";
+                if !text.is_empty() {
+                    args.push(text.to_string());
+                }
+            },
+        }
+    }
+
+    if rest.trim().is_empty() {
+        Some(args)
+    } else {
+        None
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &'static str = "951C88B7E75C867418ACDB5D273821372BB5BD652740BCDF623A4FA293E75D2F";
+
+    fn nothing(_: &mut (), _: &Call) {}
+
+    fn make(name: &'static str, aliases: &'static [&'static str],
+            args: &'static [Arg], scope: Scope) -> Command<()> {
+        Command {
+            name: name,
+            aliases: aliases,
+            args: args,
+            scope: scope,
+            description: "",
+            examples: &[],
+            handler: nothing,
+        }
+    }
+
+    fn registry(prefix: &str) -> Registry<()> {
+        let mut registry = Registry::new(prefix);
+        registry.register(make("help", &["commands"], &[Arg::OptWord("command")],
+                               Scope::Both));
+        registry.register(make("id", &[], &[], Scope::Both));
+        registry.register(make("say", &[], &[Arg::Word("word")], Scope::Both));
+        registry.register(make("invite", &[], &[Arg::OptText("group")],
+                               Scope::Friend));
+        registry
+    }
+
+    /*
+        Command name and arguments, `None` for a normal message, or
+        message that should be sent back for invalid command.
+    */
+    fn parse(registry: &Registry<()>, msg: &str, chat: Chat)
+        -> Result<Option<(&'static str, Vec<String>)>, String>
+    {
+        let pk: PublicKey = KEY.parse().unwrap();
+        match registry.parse(msg, chat, &pk) {
+            Parsed::NotCommand => Ok(None),
+            Parsed::Command(_, call) => Ok(Some((call.name, call.args))),
+            Parsed::Invalid(_, reply) => Err(reply),
+        }
+    }
+
+    fn command(name: &'static str, args: &[&str])
+        -> Result<Option<(&'static str, Vec<String>)>, String>
+    {
+        Ok(Some((name, args.iter().map(|a| a.to_string()).collect())))
+    }
+
+    #[test]
+    fn prefixed_commands_are_parsed() {
+        let registry = registry(".");
+        let group = Chat::Group(0);
+        assert_eq!(parse(&registry, ".help", group), command("help", &[]));
+        assert_eq!(parse(&registry, ".commands say", group),
+                   command("help", &["say"]));
+        assert_eq!(parse(&registry, ".invite public chat", Chat::Friend(0)),
+                   command("invite", &["public chat"]));
+        assert_eq!(parse(&registry, "help", group), Ok(None));
+        assert_eq!(parse(&registry, ".unknown", group), Ok(None));
+    }
+
+    #[test]
+    fn invalid_commands_get_reply() {
+        let registry = registry(".");
+        assert_eq!(parse(&registry, ".say", Chat::Group(0)),
+                   Err("Usage: .say <word>".to_string()));
+        assert_eq!(parse(&registry, ".id now", Chat::Group(0)),
+                   Err("Usage: .id".to_string()));
+        assert_eq!(parse(&registry, ".invite", Chat::Group(0)),
+                   Err("`.invite` can't be used in groupchats.".to_string()));
+    }
+
+    #[test]
+    fn bridge_prefix_is_skipped() {
+        let registry = registry(".");
+        let group = Chat::Group(0);
+        assert_eq!(parse(&registry, "[nick] .help", group), command("help", &[]));
+        assert_eq!(parse(&registry, "<nick> .help", group), command("help", &[]));
+        assert_eq!(parse(&registry, "<nick>.say hi", group),
+                   command("say", &["hi"]));
+        assert_eq!(parse(&registry, "[nick] help", group), Ok(None));
+        assert_eq!(parse(&registry, "so [nick] .help", group), Ok(None));
+    }
+
+    #[test]
+    fn custom_prefix_is_used() {
+        let registry = registry("!!");
+        let group = Chat::Group(0);
+        assert_eq!(parse(&registry, "!!help id", group), command("help", &["id"]));
+        assert_eq!(parse(&registry, "[nick] !!id", group), command("id", &[]));
+        assert_eq!(parse(&registry, ".help", group), Ok(None));
+        assert_eq!(registry.usage(registry.find("!!say").unwrap()), "!!say <word>");
+    }
+
+    #[test]
+    fn friend_commands_can_be_bare() {
+        let registry = registry(".");
+        let friend = Chat::Friend(0);
+        assert_eq!(parse(&registry, "id", friend), command("id", &[]));
+        assert_eq!(parse(&registry, "invite", friend), command("invite", &[]));
+        assert_eq!(parse(&registry, " invite ", friend), command("invite", &[]));
+        assert_eq!(parse(&registry, ".invite 0", friend), command("invite", &["0"]));
+
+        // messages that just start with a name of a command
+        assert_eq!(parse(&registry, "invite me", friend), Ok(None));
+        assert_eq!(parse(&registry, "id please", friend), Ok(None));
+        assert_eq!(parse(&registry, "say hi", friend), Ok(None));
+        // command that takes arguments, even if they're optional
+        assert_eq!(parse(&registry, "help", friend), Ok(None));
+        // bare commands are only for friend chats
+        assert_eq!(parse(&registry, "id", Chat::Group(0)), Ok(None));
+    }
+}
@@ -0,0 +1,1012 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Configuration file support.
+
+    Config file is written in TOML, and every value in it is optional –
+    missing values fall back to defaults, which are the same as values that
+    used to be hardcoded in Lee.
+
+    Unknown sections and keys are treated as errors, rather than ignored, so
+    that a typo in config file wouldn't silently leave default in place.
+*/
+
+use std::io::prelude::*;
+use std::fs::{self, File};
+use std::time::SystemTime;
+
+extern crate toml;
+use self::toml::{Parser, Table, Value};
+
+use friend_requests::{self, Policy};
+use permissions::{self, Permissions, Role};
+use filter::{self, Rules};
+use impostor::ImpostorConfig;
+use logging::{Filter, LogConfig};
+use ratelimit::{LimitConfig, Quota};
+use triggers::{TriggerConfig, Triggers};
+
+
+/**
+    Default name of config file, looked for in working directory.
+*/
+pub const CONFIG_FILE: &'static str = "lee.toml";
+
+
+/**
+    Bot configuration.
+*/
+#[derive(Clone, Debug)]
+pub struct Config {
+    /**
+        Name that Lee will use in Tox network.
+    */
+    pub name: String,
+
+    /**
+        Default status message.
+    */
+    pub status_message: String,
+
+    /**
+        Path to Tox save file.
+    */
+    pub tox_save: String,
+
+    /**
+        Path to file with saved markov chain.
+    */
+    pub markov_file: String,
+
+    /**
+        Path to plaintext file used to feed markov chain when there is no
+        saved chain.
+    */
+    pub markov_txt: String,
+
+    /**
+        Path to file with hashes of messages already fed to markov chain.
+    */
+    pub hashes_file: String,
+
+    /**
+        Path to file with every message that Lee has learned, from which
+        markov chain can be rebuilt.
+    */
+    pub corpus_file: String,
+
+    /**
+        Path to file with public keys of ignored peers.
+    */
+    pub ignore_file: String,
+
+    /**
+        Order of markov chain, i.e. how many previous words are used to
+        pick the next one.
+    */
+    pub chain_order: usize,
+
+    /**
+        Whether every groupchat should have its own chain, in addition to
+        the global one.
+    */
+    pub group_chains: bool,
+
+    /**
+        Directory in which chains of groupchats are saved.
+    */
+    pub group_chains_dir: String,
+
+    /**
+        Number of messages that chain of a groupchat has to learn before
+        it's used instead of the global one.
+    */
+    pub group_chain_min: u64,
+
+    /**
+        Chance of using the global chain even when chain of a groupchat is
+        big enough, in range `0.0 – 1.0`.
+    */
+    pub group_chain_blend: f64,
+
+    /**
+        Max number of hashes of messages that are remembered. When there
+        are more of them, the oldest ones are forgotten.
+    */
+    pub max_hashes: usize,
+
+    /**
+        Number of backups kept for each saved file.
+    */
+    pub backups: usize,
+
+    /**
+        Max time between first unsaved change and save, in seconds.
+    */
+    pub save_interval: i64,
+
+    /**
+        Time without new changes after which data is saved, in seconds.
+    */
+    pub save_debounce: i64,
+
+    /**
+        Interval between attempts to talk randomly, in seconds.
+    */
+    pub talk_interval: i64,
+
+    /**
+        Chance that Lee will talk at given attempt, in range `0.0 – 1.0`.
+    */
+    pub talk_chance: f64,
+
+    /**
+        Path to file with bootstrap nodes, either plaintext or JSON.
+    */
+    pub bootstrap_file: String,
+
+    /**
+        Number of random nodes from bootstrap file to bootstrap from.
+    */
+    pub bootstrap_nodes: usize,
+
+    /**
+        Policy of accepting friend requests.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub friend_requests: Policy,
+
+    /**
+        Roles of peers and permissions required to use commands.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub permissions: Permissions,
+
+    /**
+        Filter rules for messages that Lee learns.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub learn_filter: Rules,
+
+    /**
+        Filter rules for messages that Lee says.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub speak_filter: Rules,
+
+    /**
+        Rate limits.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub rate_limits: LimitConfig,
+
+    /**
+        Impostor detection.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub impostors: ImpostorConfig,
+
+    /**
+        What triggers Lee's response in groupchats.
+
+        Can be changed without restart, by editing config file.
+    */
+    pub triggers: TriggerConfig,
+
+    /**
+        Prefix that commands have to start with.
+    */
+    pub command_prefix: String,
+
+    /**
+        Logging configuration.
+    */
+    pub logging: LogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            name: "Lee".to_string(),
+            status_message: "Send me a message 'invite' to get into the groupchat".to_string(),
+            tox_save: "lee.tox".to_string(),
+            markov_file: "markov.json".to_string(),
+            markov_txt: "markov.txt".to_string(),
+            hashes_file: "markov.hashes".to_string(),
+            corpus_file: "corpus.jsonl".to_string(),
+            ignore_file: "ignore.txt".to_string(),
+            chain_order: 1,
+            group_chains: false,
+            group_chains_dir: "chains".to_string(),
+            group_chain_min: 200,
+            group_chain_blend: 0.2,
+            max_hashes: 100000,
+            backups: 3,
+            save_interval: 64,
+            save_debounce: 5,
+            talk_interval: 10,
+            talk_chance: 0.01,
+            bootstrap_file: "bootstrap.txt".to_string(),
+            bootstrap_nodes: 4,
+            friend_requests: Policy::default(),
+            permissions: Permissions::default(),
+            learn_filter: Rules::default(),
+            speak_filter: Rules::default(),
+            rate_limits: LimitConfig::default(),
+            impostors: ImpostorConfig::default(),
+            triggers: TriggerConfig::default(),
+            command_prefix: ".".to_string(),
+            logging: LogConfig::default(),
+        }
+    }
+}
+
+
+impl Config {
+    /**
+        Load config from a file.
+
+        Any error, be it missing file, failure to read it, invalid TOML or
+        unknown or invalid key, is returned as `Err`, so that it could be
+        printed.
+    */
+    pub fn load(path: &str) -> Result<Config, String> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening `{}`: {}", path, e)),
+        };
+
+        let mut content = String::new();
+        if let Err(e) = file.read_to_string(&mut content) {
+            return Err(format!("Error reading `{}`: {}", path, e));
+        }
+
+        Config::parse(&content)
+            .map_err(|e| format!("Error in `{}`: {}", path, e))
+    }
+
+    /**
+        Load config from a file, using default config in case where file
+        doesn't exist.
+
+        Meant only for the implicit `lee.toml` – a file that user asked for
+        and that isn't there is an error.
+    */
+    pub fn load_or_default(path: &str) -> Result<Config, String> {
+        match File::open(path) {
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound =>
+                Ok(Config::default()),
+            _ => Config::load(path),
+        }
+    }
+
+    /**
+        Parse config from a string.
+    */
+    pub fn parse(content: &str) -> Result<Config, String> {
+        let mut parser = Parser::new(content);
+        let table = match parser.parse() {
+            Some(t) => t,
+            None => {
+                let errors: Vec<String> = parser.errors.iter().map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("line {}, column {}: {}", line + 1, col + 1, e.desc)
+                }).collect();
+                return Err(errors.join("; "));
+            },
+        };
+
+        let mut config = Config::default();
+        for (section, value) in &table {
+            let values = match *value {
+                Value::Table(ref t) => t,
+                _ => return Err(format!("`{}` should be a section", section)),
+            };
+
+            try!(match &**section {
+                "bot"    => config.parse_bot(values),
+                "files"  => config.parse_files(values),
+                "timers" => config.parse_timers(values),
+                "bootstrap" => config.parse_bootstrap(values),
+                "dedup" => config.parse_dedup(values),
+                "markov" => config.parse_markov(values),
+                "friend_requests" => config.parse_friend_requests(values),
+                "roles" => config.parse_roles(values),
+                "permissions" => config.parse_permissions(values),
+                "commands" => config.parse_commands(values),
+                "filter" => config.parse_filter(values),
+                "rate_limits" => config.parse_rate_limits(values),
+                "impostors" => config.parse_impostors(values),
+                "triggers" => config.parse_triggers(values),
+                "logging" => config.parse_logging(values),
+                _ => Err(format!("unknown section `[{}]`", section)),
+            });
+        }
+
+        Ok(config)
+    }
+
+
+    fn parse_bot(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "name" => self.name = try!(get_str("bot", key, value)),
+                "status_message" =>
+                    self.status_message = try!(get_str("bot", key, value)),
+                _ => return Err(unknown_key("bot", key)),
+            }
+        }
+
+        if self.name.is_empty() {
+            return Err("`bot.name` can't be empty".to_string());
+        }
+        Ok(())
+    }
+
+    fn parse_files(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "tox_save" => self.tox_save = try!(get_str("files", key, value)),
+                "markov" => self.markov_file = try!(get_str("files", key, value)),
+                "markov_txt" =>
+                    self.markov_txt = try!(get_str("files", key, value)),
+                "hashes" => self.hashes_file = try!(get_str("files", key, value)),
+                "corpus" => self.corpus_file = try!(get_str("files", key, value)),
+                "ignore" => self.ignore_file = try!(get_str("files", key, value)),
+                "backups" => {
+                    let backups = try!(get_int("files", key, value));
+                    if backups < 0 {
+                        return Err(format!("`files.backups` can't be \
+                                            negative, got {}", backups));
+                    }
+                    self.backups = backups as usize;
+                },
+                _ => return Err(unknown_key("files", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_timers(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "save_interval" =>
+                    self.save_interval = try!(get_positive("timers", key, value)),
+                "save_debounce" =>
+                    self.save_debounce = try!(get_positive("timers", key, value)),
+                "talk_interval" =>
+                    self.talk_interval = try!(get_positive("timers", key, value)),
+                "talk_chance" => {
+                    let chance = try!(get_float("timers", key, value));
+                    if chance < 0.0 || chance > 1.0 {
+                        return Err(format!("`timers.{}` should be between \
+                                            0.0 and 1.0, got {}", key, chance));
+                    }
+                    self.talk_chance = chance;
+                },
+                _ => return Err(unknown_key("timers", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_bootstrap(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "file" => self.bootstrap_file = try!(get_str("bootstrap", key, value)),
+                "nodes" => {
+                    let nodes = try!(get_positive("bootstrap", key, value));
+                    if (nodes as usize) < ::bootstrap::MIN_NODES {
+                        return Err(format!("`bootstrap.nodes` should be at \
+                                            least {}, got {}",
+                                           ::bootstrap::MIN_NODES, nodes));
+                    }
+                    self.bootstrap_nodes = nodes as usize;
+                },
+                _ => return Err(unknown_key("bootstrap", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_dedup(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "max_hashes" =>
+                    self.max_hashes = try!(get_positive("dedup", key, value)) as usize,
+                _ => return Err(unknown_key("dedup", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_markov(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "order" =>
+                    self.chain_order = try!(get_positive("markov", key, value)) as usize,
+                "per_group" =>
+                    self.group_chains = try!(get_bool("markov", key, value)),
+                "group_dir" =>
+                    self.group_chains_dir = try!(get_str("markov", key, value)),
+                "group_min_messages" =>
+                    self.group_chain_min = try!(get_positive("markov", key, value)) as u64,
+                "group_blend" => {
+                    let chance = try!(get_float("markov", key, value));
+                    if chance < 0.0 || chance > 1.0 {
+                        return Err(format!("`markov.{}` should be between \
+                                            0.0 and 1.0, got {}", key, chance));
+                    }
+                    self.group_chain_blend = chance;
+                },
+                _ => return Err(unknown_key("markov", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_friend_requests(&mut self, table: &Table) -> Result<(), String> {
+        const SECTION: &'static str = "friend_requests";
+        let mut policy = "all".to_string();
+        let mut allowlist = vec![];
+        let mut denylist = vec![];
+        let mut passphrase = String::new();
+
+        for (key, value) in table {
+            match &**key {
+                "policy" => policy = try!(get_str(SECTION, key, value)),
+                "allowlist" => allowlist = try!(get_keys(SECTION, key, value)),
+                "denylist" => denylist = try!(get_keys(SECTION, key, value)),
+                "passphrase" => passphrase = try!(get_str(SECTION, key, value)),
+                _ => return Err(unknown_key(SECTION, key)),
+            }
+        }
+
+        self.friend_requests = match &*policy {
+            "all" => Policy::AcceptAll,
+            "allowlist" => Policy::Allowlist(allowlist),
+            "denylist" => Policy::Denylist(denylist),
+            "passphrase" => {
+                if passphrase.is_empty() {
+                    return Err("`friend_requests.passphrase` can't be empty \
+                                when policy is \"passphrase\"".to_string());
+                }
+                Policy::Passphrase(passphrase)
+            },
+            p => return Err(format!("`friend_requests.policy` should be one \
+                                     of \"all\", \"allowlist\", \"denylist\", \
+                                     \"passphrase\", got {:?}", p)),
+        };
+        Ok(())
+    }
+
+    fn parse_roles(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "owners" =>
+                    self.permissions.owners = try!(get_keys("roles", key, value)),
+                "moderators" =>
+                    self.permissions.moderators = try!(get_keys("roles", key, value)),
+                _ => return Err(unknown_key("roles", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_commands(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "prefix" => {
+                    let prefix = try!(get_str("commands", key, value));
+                    if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+                        return Err(format!("`commands.prefix` should be \
+                                            non-empty and without spaces, \
+                                            got {:?}", prefix));
+                    }
+                    self.command_prefix = prefix;
+                },
+                _ => return Err(unknown_key("commands", key)),
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Filter has two subsections, `[filter.learn]` and `[filter.speak]`,
+        with the same keys.
+    */
+    fn parse_filter(&mut self, table: &Table) -> Result<(), String> {
+        for (sub, value) in table {
+            let section = format!("filter.{}", sub);
+            let values = match *value {
+                Value::Table(ref t) => t,
+                _ => return Err(format!("`{}` should be a section", section)),
+            };
+            let rules = match &**sub {
+                "learn" => &mut self.learn_filter,
+                "speak" => &mut self.speak_filter,
+                _ => return Err(format!("unknown section `[{}]`", section)),
+            };
+            try!(parse_rules(rules, &section, values));
+        }
+        Ok(())
+    }
+
+    fn parse_rate_limits(&mut self, table: &Table) -> Result<(), String> {
+        const SECTION: &'static str = "rate_limits";
+        for (key, value) in table {
+            match &**key {
+                "cooldown" => {
+                    let cooldown = try!(get_int(SECTION, key, value));
+                    if cooldown < 0 {
+                        return Err(format!("`rate_limits.cooldown` can't be \
+                                            negative, got {}", cooldown));
+                    }
+                    self.rate_limits.cooldown = cooldown;
+                },
+                "peer_triggers" =>
+                    self.rate_limits.peer_triggers = try!(get_quota(SECTION, key, value)),
+                "peer_commands" =>
+                    self.rate_limits.peer_commands = try!(get_quota(SECTION, key, value)),
+                "friend_replies" =>
+                    self.rate_limits.friend_replies = try!(get_quota(SECTION, key, value)),
+                "group_triggers" =>
+                    self.rate_limits.group_triggers = try!(get_quota(SECTION, key, value)),
+                "group_commands" =>
+                    self.rate_limits.group_commands = try!(get_quota(SECTION, key, value)),
+                _ => return Err(unknown_key(SECTION, key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_impostors(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "enabled" =>
+                    self.impostors.enabled = try!(get_bool("impostors", key, value)),
+                "warning" =>
+                    self.impostors.warning = try!(get_str("impostors", key, value)),
+                "interval" => {
+                    let interval = try!(get_int("impostors", key, value));
+                    if interval < 0 {
+                        return Err(format!("`impostors.interval` can't be \
+                                            negative, got {}", interval));
+                    }
+                    self.impostors.interval = interval;
+                },
+                _ => return Err(unknown_key("impostors", key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_triggers(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "aliases" =>
+                    self.triggers.aliases = try!(get_str_array("triggers", key, value)),
+                "patterns" =>
+                    self.triggers.patterns = try!(get_str_array("triggers", key, value)),
+                "question_chance" => {
+                    let chance = try!(get_float("triggers", key, value));
+                    if chance < 0.0 || chance > 1.0 {
+                        return Err(format!("`triggers.{}` should be between \
+                                            0.0 and 1.0, got {}", key, chance));
+                    }
+                    self.triggers.question_chance = chance;
+                },
+                _ => return Err(unknown_key("triggers", key)),
+            }
+        }
+
+        if let Err(e) = Triggers::new(&self.name, self.triggers.clone()) {
+            return Err(format!("`triggers.patterns`: {}", e));
+        }
+        Ok(())
+    }
+
+    fn parse_logging(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            match &**key {
+                "filter" => {
+                    let filter = try!(get_str("logging", key, value));
+                    if let Err(e) = Filter::parse(&filter) {
+                        return Err(format!("`logging.filter`: {}", e));
+                    }
+                    self.logging.filter = filter;
+                },
+                "format" => {
+                    self.logging.json = match &*try!(get_str("logging", key, value)) {
+                        "text" => false,
+                        "json" => true,
+                        f => return Err(format!("`logging.format` should be \
+                                                 \"text\" or \"json\", got \
+                                                 {:?}", f)),
+                    };
+                },
+                "stdout" => self.logging.stdout = try!(get_bool("logging", key, value)),
+                "file" => {
+                    let file = try!(get_str("logging", key, value));
+                    self.logging.file = if file.is_empty() { None } else { Some(file) };
+                },
+                "max_size" =>
+                    self.logging.max_size = try!(get_positive("logging", key, value)) as u64,
+                "max_files" => {
+                    let files = try!(get_int("logging", key, value));
+                    if files < 0 {
+                        return Err(format!("`logging.max_files` can't be \
+                                            negative, got {}", files));
+                    }
+                    self.logging.max_files = files as usize;
+                },
+                _ => return Err(unknown_key("logging", key)),
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Every key is a name of a command, and value is a role required to
+        use it.
+    */
+    fn parse_permissions(&mut self, table: &Table) -> Result<(), String> {
+        for (key, value) in table {
+            if !permissions::COMMANDS.contains(&&**key) {
+                return Err(format!("unknown command `{}` in section \
+                                    `[permissions]`", key));
+            }
+
+            let role = try!(get_str("permissions", key, value));
+            match Role::parse(&role) {
+                Some(r) => {
+                    self.permissions.commands.insert(key.clone(), r);
+                },
+                None => return Err(format!("`permissions.{}` should be one of \
+                                            \"user\", \"moderator\", \"owner\", \
+                                            got {:?}", key, role)),
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/*
+    Helpers for getting values of expected type out of TOML, with errors
+    that tell where the wrong value is.
+*/
+fn unknown_key(section: &str, key: &str) -> String {
+    format!("unknown key `{}` in section `[{}]`", key, section)
+}
+
+fn wrong_type(section: &str, key: &str, expected: &str, value: &Value) -> String {
+    format!("`{}.{}` should be {}, got {}",
+            section, key, expected, value.type_str())
+}
+
+fn get_str(section: &str, key: &str, value: &Value) -> Result<String, String> {
+    match value.as_str() {
+        Some(s) => Ok(s.to_string()),
+        None => Err(wrong_type(section, key, "a string", value)),
+    }
+}
+
+fn get_int(section: &str, key: &str, value: &Value) -> Result<i64, String> {
+    match value.as_integer() {
+        Some(i) => Ok(i),
+        None => Err(wrong_type(section, key, "an integer", value)),
+    }
+}
+
+fn get_positive(section: &str, key: &str, value: &Value) -> Result<i64, String> {
+    let i = try!(get_int(section, key, value));
+    if i <= 0 {
+        return Err(format!("`{}.{}` should be greater than 0, got {}",
+                           section, key, i));
+    }
+    Ok(i)
+}
+
+/*
+    Integers are accepted as well, so that `talk_chance = 1` would work.
+*/
+fn get_float(section: &str, key: &str, value: &Value) -> Result<f64, String> {
+    match *value {
+        Value::Float(f) => Ok(f),
+        Value::Integer(i) => Ok(i as f64),
+        _ => Err(wrong_type(section, key, "a number", value)),
+    }
+}
+
+fn get_bool(section: &str, key: &str, value: &Value) -> Result<bool, String> {
+    match value.as_bool() {
+        Some(b) => Ok(b),
+        None => Err(wrong_type(section, key, "a boolean", value)),
+    }
+}
+
+fn parse_rules(rules: &mut Rules, section: &str, table: &Table)
+    -> Result<(), String>
+{
+    for (key, value) in table {
+        match &**key {
+            "words" => {
+                let words = try!(get_str_array(section, key, value));
+                if let Some(w) = words.iter().find(|w| {
+                    w.is_empty() || !w.chars().all(char::is_alphanumeric)
+                }) {
+                    return Err(format!("`{}.words`: {:?} isn't a single word, \
+                                        use `patterns` for phrases or words \
+                                        with punctuation", section, w));
+                }
+                rules.words = words;
+            },
+            "patterns" => rules.patterns = try!(get_str_array(section, key, value)),
+            "strip_urls" => rules.strip_urls = try!(get_bool(section, key, value)),
+            "strip_tox_ids" =>
+                rules.strip_tox_ids = try!(get_bool(section, key, value)),
+            "max_length" => {
+                let max = try!(get_int(section, key, value));
+                if max < 0 {
+                    return Err(format!("`{}.max_length` can't be negative, \
+                                        got {}", section, max));
+                }
+                rules.max_length = max as usize;
+            },
+            _ => return Err(unknown_key(section, key)),
+        }
+    }
+
+    if let Err(e) = filter::Filter::new(rules) {
+        return Err(format!("`{}.patterns`: {}", section, e));
+    }
+    Ok(())
+}
+
+/*
+    Quota, written as `[burst, per_minute]`.
+*/
+fn get_quota(section: &str, key: &str, value: &Value) -> Result<Quota, String> {
+    const EXPECTED: &'static str = "an array of two non-negative numbers";
+    let array = match value.as_slice() {
+        Some(a) if a.len() == 2 => a,
+        _ => return Err(wrong_type(section, key, EXPECTED, value)),
+    };
+
+    let mut numbers = [0.0; 2];
+    for (n, v) in numbers.iter_mut().zip(array) {
+        *n = match *v {
+            Value::Float(f) if f >= 0.0 => f,
+            Value::Integer(i) if i >= 0 => i as f64,
+            _ => return Err(wrong_type(section, key, EXPECTED, value)),
+        };
+    }
+    Ok(Quota::new(numbers[0], numbers[1]))
+}
+
+fn get_str_array(section: &str, key: &str, value: &Value)
+    -> Result<Vec<String>, String>
+{
+    let array = match value.as_slice() {
+        Some(a) => a,
+        None => return Err(wrong_type(section, key, "an array of strings", value)),
+    };
+
+    let mut strings = Vec::with_capacity(array.len());
+    for v in array {
+        match v.as_str() {
+            Some(s) => strings.push(s.to_string()),
+            None => return Err(wrong_type(section, key, "an array of strings", value)),
+        }
+    }
+    Ok(strings)
+}
+
+/*
+    Array of public keys, or Tox IDs, normalized to uppercase public keys.
+*/
+fn get_keys(section: &str, key: &str, value: &Value) -> Result<Vec<String>, String> {
+    let mut keys = vec![];
+    for k in try!(get_str_array(section, key, value)) {
+        match friend_requests::normalize_key(&k) {
+            Some(pk) => keys.push(pk),
+            None => return Err(format!("`{}.{}`: {:?} is not a valid public \
+                                        key or Tox ID", section, key, k)),
+        }
+    }
+    Ok(keys)
+}
+
+
+
+/**
+    Watches config file for changes, so that it could be reloaded without
+    restarting Lee.
+
+    Modification time of a file is checked no more often than every
+    `CHECK_INTERVAL` seconds.
+*/
+pub struct ConfigWatcher {
+    pub path: String,
+    mtime: Option<SystemTime>,
+    last_check: i64,
+}
+
+const CHECK_INTERVAL: i64 = 5;
+
+impl ConfigWatcher {
+    pub fn new(path: &str) -> ConfigWatcher {
+        ConfigWatcher {
+            path: path.to_string(),
+            mtime: modified(path),
+            last_check: 0,
+        }
+    }
+
+    /**
+        Check whether config file has changed since the last time it was
+        checked.
+    */
+    pub fn changed(&mut self, cur_time: i64) -> bool {
+        if self.last_check + CHECK_INTERVAL > cur_time {
+            return false;
+        }
+        self.last_check = cur_time;
+
+        let mtime = modified(&self.path);
+        if mtime != self.mtime {
+            self.mtime = mtime;
+            return true;
+        }
+        false
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &'static str = "951C88B7E75C867418ACDB5D273821372BB5BD652740BCDF623A4FA293E75D2F";
+
+    fn error(content: &str) -> String {
+        Config::parse(content).unwrap_err()
+    }
+
+    #[test]
+    fn empty_config_is_default() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.name, "Lee");
+        assert_eq!(config.chain_order, 1);
+        assert_eq!(config.command_prefix, ".");
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let content = "[bot]\nname = \"Lea\"\n\
+                       [markov]\norder = 2\n\
+                       [timers]\ntalk_chance = 1\n\
+                       [bootstrap]\nnodes = 4\n\
+                       [rate_limits]\npeer_triggers = [3.0, 1.5]\n\
+                       [commands]\nprefix = \"!\"\n";
+        let config = Config::parse(content).unwrap();
+        assert_eq!(config.name, "Lea");
+        assert_eq!(config.chain_order, 2);
+        assert_eq!(config.talk_chance, 1.0);
+        assert_eq!(config.bootstrap_nodes, 4);
+        assert_eq!(config.rate_limits.peer_triggers, Quota::new(3.0, 1.5));
+        assert_eq!(config.command_prefix, "!");
+    }
+
+    #[test]
+    fn unknown_keys_and_sections_are_errors() {
+        assert_eq!(error("[bot]\nnmae = \"Lea\""),
+                   "unknown key `nmae` in section `[bot]`");
+        assert_eq!(error("[bots]\nname = \"Lea\""), "unknown section `[bots]`");
+        assert_eq!(error("[filter.lean]\nwords = []"),
+                   "unknown section `[filter.lean]`");
+        assert_eq!(error("bot = 1"), "`bot` should be a section");
+    }
+
+    #[test]
+    fn wrong_types_are_errors() {
+        assert_eq!(error("[bot]\nname = 1"),
+                   "`bot.name` should be a string, got integer");
+        assert_eq!(error("[markov]\norder = \"2\""),
+                   "`markov.order` should be an integer, got string");
+        assert_eq!(error("[timers]\ntalk_chance = true"),
+                   "`timers.talk_chance` should be a number, got boolean");
+        assert_eq!(error("[triggers]\naliases = [1]"),
+                   "`triggers.aliases` should be an array of strings, got array");
+    }
+
+    #[test]
+    fn values_out_of_range_are_errors() {
+        assert_eq!(error("[bootstrap]\nnodes = 3"),
+                   "`bootstrap.nodes` should be at least 4, got 3");
+        assert_eq!(error("[markov]\norder = 0"),
+                   "`markov.order` should be greater than 0, got 0");
+        assert_eq!(error("[timers]\ntalk_chance = 1.5"),
+                   "`timers.talk_chance` should be between 0.0 and 1.0, got 1.5");
+        assert_eq!(error("[markov]\ngroup_blend = -0.1"),
+                   "`markov.group_blend` should be between 0.0 and 1.0, got -0.1");
+        assert_eq!(error("[files]\nbackups = -1"),
+                   "`files.backups` can't be negative, got -1");
+    }
+
+    #[test]
+    fn quotas_are_pairs_of_non_negative_numbers() {
+        const EXPECTED: &'static str = "`rate_limits.peer_commands` should be \
+                                        an array of two non-negative numbers, \
+                                        got array";
+        assert_eq!(error("[rate_limits]\npeer_commands = [1]"), EXPECTED);
+        assert_eq!(error("[rate_limits]\npeer_commands = [1, 2, 3]"), EXPECTED);
+        assert_eq!(error("[rate_limits]\npeer_commands = [1, -2]"), EXPECTED);
+        assert_eq!(error("[rate_limits]\npeer_commands = 1"),
+                   "`rate_limits.peer_commands` should be an array of two \
+                    non-negative numbers, got integer");
+        let config = Config::parse("[rate_limits]\npeer_commands = [0, 0]").unwrap();
+        assert_eq!(config.rate_limits.peer_commands, Quota::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn roles_and_permissions_are_parsed() {
+        let content = format!("[roles]\nowners = [\"{}\"]\n\
+                               [permissions]\nsay = \"moderator\"\n",
+                              KEY.to_lowercase());
+        let config = Config::parse(&content).unwrap();
+        assert_eq!(config.permissions.owners, vec![KEY.to_string()]);
+        assert_eq!(config.permissions.commands.get("say"), Some(&Role::Moderator));
+
+        assert_eq!(error("[permissions]\nsay = \"admin\""),
+                   "`permissions.say` should be one of \"user\", \
+                    \"moderator\", \"owner\", got \"admin\"");
+        assert_eq!(error("[permissions]\nfly = \"user\""),
+                   "unknown command `fly` in section `[permissions]`");
+    }
+
+    #[test]
+    fn keys_and_tox_ids_are_accepted() {
+        let tox_id = format!("{}{}", KEY, "0123456789AB");
+        let content = format!("[friend_requests]\npolicy = \"allowlist\"\n\
+                               allowlist = [\"{}\", \"{}\"]\n", KEY, tox_id);
+        match Config::parse(&content).unwrap().friend_requests {
+            Policy::Allowlist(keys) =>
+                assert_eq!(keys, vec![KEY.to_string(), KEY.to_string()]),
+            p => panic!("unexpected policy {:?}", p),
+        }
+
+        assert_eq!(error("[roles]\nmoderators = [\"Lee\"]"),
+                   "`roles.moderators`: \"Lee\" is not a valid public key \
+                    or Tox ID");
+    }
+}
@@ -0,0 +1,288 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Corpus – every message that Lee has learned, in order in which they were
+    learned.
+
+    Markov chain can't be changed after something was fed to it, e.g. to a
+    different order, but it can be rebuilt from the corpus.
+
+    Corpus is a journal written as JSON lines, one object per learned
+    message, and it's only ever appended to – except when something is
+    forgotten. Along with message, it's recorded when it was learned, where
+    it came from, and who sent it, e.g.:
+
+        {"group":"Tox Public Chat","id":0,"sender":"04119E…","source":"group","text":"hi","time":1440000000}
+
+    Entries written before that was recorded have only `time` and `text`.
+*/
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use chrono::UTC;
+
+use rstox::core::PublicKey;
+
+use regex::Regex;
+
+use for_files;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::Json;
+
+
+/**
+    Where message came from.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /**
+        Entry written before sources were recorded.
+    */
+    Unknown,
+
+    /**
+        Friend with given friend number.
+    */
+    Friend(u32),
+
+    /**
+        Groupchat with given number, and title, if it had one.
+    */
+    Group(i32, Option<String>),
+}
+
+
+/**
+    Message from the corpus.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /**
+        UNIX time when message was learned.
+    */
+    pub time: i64,
+
+    pub text: String,
+
+    pub source: Source,
+
+    /**
+        Uppercase public key of peer who sent message, if it's known.
+    */
+    pub sender: Option<String>,
+}
+
+impl Entry {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("time".to_string(), Json::I64(self.time));
+        obj.insert("text".to_string(), Json::String(self.text.clone()));
+
+        match self.source {
+            Source::Unknown => {},
+            Source::Friend(fnum) => {
+                obj.insert("source".to_string(), Json::String("friend".to_string()));
+                obj.insert("id".to_string(), Json::I64(fnum as i64));
+            },
+            Source::Group(gnum, ref title) => {
+                obj.insert("source".to_string(), Json::String("group".to_string()));
+                obj.insert("id".to_string(), Json::I64(gnum as i64));
+                if let Some(ref title) = *title {
+                    obj.insert("group".to_string(), Json::String(title.clone()));
+                }
+            },
+        }
+
+        if let Some(ref sender) = self.sender {
+            obj.insert("sender".to_string(), Json::String(sender.clone()));
+        }
+        Json::Object(obj)
+    }
+
+    fn from_json(json: &Json) -> Option<Entry> {
+        let obj = match json.as_object() {
+            Some(o) => o,
+            None => return None,
+        };
+        let text = match obj.get("text").and_then(|t| t.as_string()) {
+            Some(t) => t.to_string(),
+            None => return None,
+        };
+
+        let id = obj.get("id").and_then(|i| i.as_i64());
+        let source = match (obj.get("source").and_then(|s| s.as_string()), id) {
+            (Some("friend"), Some(id)) => Source::Friend(id as u32),
+            (Some("group"), Some(id)) => Source::Group(id as i32,
+                obj.get("group").and_then(|g| g.as_string()).map(|g| g.to_string())),
+            (None, _) => Source::Unknown,
+            _ => return None,
+        };
+
+        Some(Entry {
+            time: obj.get("time").and_then(|t| t.as_i64()).unwrap_or(0),
+            text: text,
+            source: source,
+            sender: obj.get("sender").and_then(|s| s.as_string())
+                        .map(|s| s.to_string()),
+        })
+    }
+}
+
+
+/**
+    What should be forgotten – either a phrase, matched without regard to
+    case, or a regular expression, written between slashes, e.g. `/a+b/`.
+*/
+pub enum Pattern {
+    Phrase(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, String> {
+        if pattern.len() > 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+            Regex::new(&pattern[1..pattern.len() - 1])
+                .map(Pattern::Regex)
+                .map_err(|e| format!("{}", e))
+        } else {
+            Ok(Pattern::Phrase(pattern.to_lowercase()))
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match *self {
+            Pattern::Phrase(ref p) => text.to_lowercase().contains(&**p),
+            Pattern::Regex(ref r) => r.is_match(text),
+        }
+    }
+}
+
+
+/**
+    Corpus file, opened for appending.
+*/
+pub struct Corpus {
+    pub path: String,
+    file: Option<File>,
+}
+
+impl Corpus {
+    pub fn new(path: &str) -> Corpus {
+        Corpus {
+            path: path.to_string(),
+            file: None,
+        }
+    }
+
+    /**
+        Append message to corpus, along with where it came from, and who
+        sent it.
+
+        File is opened when first message is appended, and kept open.
+    */
+    pub fn append(&mut self, text: &str, source: Source,
+                  sender: Option<&PublicKey>) -> Result<(), String> {
+        if self.file.is_none() {
+            let file = try!(OpenOptions::new().create(true).append(true)
+                                .open(&self.path)
+                                .map_err(|e| format!("{}", e)));
+            self.file = Some(file);
+        }
+
+        let entry = Entry {
+            time: UTC::now().timestamp(),
+            text: text.to_string(),
+            source: source,
+            sender: sender.map(|pk| format!("{}", pk).to_uppercase()),
+        };
+        let line = format!("{}\n", entry.to_json());
+        match self.file {
+            Some(ref mut f) => f.write_all(line.as_bytes())
+                                .map_err(|e| format!("{}", e)),
+            None => unreachable!(),
+        }
+    }
+
+    /**
+        Replace corpus with given entries, e.g. to forget some of them.
+
+        Since backups would still have entries that aren't there anymore,
+        they are removed, up to `backups` of them.
+    */
+    pub fn rewrite(&mut self, entries: &[Entry], backups: usize)
+        -> Result<(), String>
+    {
+        let mut data = vec![];
+        for entry in entries {
+            drop(writeln!(data, "{}", entry.to_json()));
+        }
+
+        // file that is open for appending would be replaced
+        self.file = None;
+        try!(for_files::write_save(&self.path, data, 0));
+        for_files::remove_backups(&self.path, backups)
+    }
+}
+
+
+/**
+    Count entries in corpus file, without parsing them. Missing file has
+    none.
+*/
+pub fn count(path: &str) -> u64 {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file).lines()
+            .filter(|l| l.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(false))
+            .count() as u64,
+        Err(_) => 0,
+    }
+}
+
+
+/**
+    Load all entries from corpus file, or from the newest of its backups
+    that can be loaded.
+
+    Lines that can't be parsed are skipped, with a warning.
+*/
+pub fn load(path: &str, backups: usize) -> Result<Vec<Entry>, String> {
+    for_files::load_with_backups(path, backups, load_file)
+}
+
+fn load_file(path: &str) -> Result<Vec<Entry>, String> {
+    let file = try!(File::open(path).map_err(|e| format!("{}", e)));
+
+    let mut entries = vec![];
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("{}", e)));
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Json::from_str(&line).ok().as_ref().and_then(Entry::from_json) {
+            Some(entry) => entries.push(entry),
+            None => warn!("`{}`, line {}: invalid entry, skipping", path, n + 1),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,235 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Filtering of messages that Lee learns, and of messages that it says.
+
+    Filter can reject a message – e.g. when it contains a blocked word, or
+    matches a blocked pattern – or strip parts of it, like URLs or Tox IDs.
+    Learning and speaking have separate rules.
+*/
+
+use regex::Regex;
+
+
+/**
+    Rules of a filter, as they're written in config.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rules {
+    /**
+        Words that make message rejected, matched as whole words, without
+        regard to case. Only letters and digits can be matched, since
+        message is split into words on everything else.
+    */
+    pub words: Vec<String>,
+
+    /**
+        Regular expressions that make message rejected, if they match any
+        part of it.
+    */
+    pub patterns: Vec<String>,
+
+    pub strip_urls: bool,
+
+    /**
+        Strip Tox IDs and public keys.
+    */
+    pub strip_tox_ids: bool,
+
+    /**
+        Max length of message, in characters. Longer messages are rejected.
+        `0` means no limit.
+    */
+    pub max_length: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            words: vec![],
+            patterns: vec![],
+            strip_urls: true,
+            strip_tox_ids: true,
+            max_length: 500,
+        }
+    }
+}
+
+
+pub struct Filter {
+    words: Vec<String>,
+    patterns: Vec<Regex>,
+    urls: Option<Regex>,
+    tox_ids: Option<Regex>,
+    max_length: usize,
+}
+
+
+impl Filter {
+    /**
+        Make filter from rules, or return error if one of patterns isn't
+        a valid regular expression.
+    */
+    pub fn new(rules: &Rules) -> Result<Filter, String> {
+        let mut patterns = Vec::with_capacity(rules.patterns.len());
+        for p in &rules.patterns {
+            patterns.push(try!(Regex::new(p)
+                .map_err(|e| format!("invalid pattern {:?}: {}", p, e))));
+        }
+
+        let urls = if rules.strip_urls {
+            Some(Regex::new(r"(?i)\b(?:[a-z][a-z0-9+.-]*://|www\.)\S+").unwrap())
+        } else {
+            None
+        };
+        let tox_ids = if rules.strip_tox_ids {
+            Some(Regex::new(r"(?i)\b(?:tox:)?[0-9a-f]{64}(?:[0-9a-f]{12})?\b").unwrap())
+        } else {
+            None
+        };
+
+        Ok(Filter {
+            words: rules.words.iter().map(|w| w.to_lowercase()).collect(),
+            patterns: patterns,
+            urls: urls,
+            tox_ids: tox_ids,
+            max_length: rules.max_length,
+        })
+    }
+
+    /**
+        Apply filter to a message.
+
+        Returns message with stripped parts removed, or `Err` with reason
+        why it was rejected. Message that is empty after stripping is
+        rejected too.
+    */
+    pub fn apply(&self, message: &str) -> Result<String, &'static str> {
+        let mut message = message.to_string();
+        if let Some(ref urls) = self.urls {
+            message = urls.replace_all(&message, "");
+        }
+        if let Some(ref tox_ids) = self.tox_ids {
+            message = tox_ids.replace_all(&message, "");
+        }
+
+        // collapse spaces left after stripping, but keep newlines
+        let message = message.lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>().join("\n");
+        let message = message.trim().to_string();
+
+        if message.is_empty() {
+            return Err("empty");
+        }
+        if self.max_length > 0 && message.chars().count() > self.max_length {
+            return Err("too long");
+        }
+
+        let lowercase = message.to_lowercase();
+        if lowercase.split(|c: char| !c.is_alphanumeric())
+            .any(|w| self.words.iter().any(|b| b == w)) {
+            return Err("blocked word");
+        }
+        if self.patterns.iter().any(|p| p.is_match(&message)) {
+            return Err("blocked pattern");
+        }
+
+        Ok(message)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &'static str = "951C88B7E75C867418ACDB5D273821372BB5BD652740BCDF623A4FA293E75D2F\
+                              5C4B41A1D1F8";
+
+    fn filter(rules: Rules) -> Filter {
+        Filter::new(&rules).unwrap()
+    }
+
+    #[test]
+    fn urls_and_tox_ids_are_stripped() {
+        let f = filter(Rules::default());
+        assert_eq!(f.apply("see https://example.com/a?b=c now"), Ok("see now".to_string()));
+        assert_eq!(f.apply("www.example.com is down"), Ok("is down".to_string()));
+        assert_eq!(f.apply(&format!("add me: tox:{} pls", ID)), Ok("add me: pls".to_string()));
+        assert_eq!(f.apply(&format!("key {} here", &ID[..64])), Ok("key here".to_string()));
+
+        let f = filter(Rules { strip_urls: false, strip_tox_ids: false, ..Rules::default() });
+        assert_eq!(f.apply("see https://example.com now"),
+                   Ok("see https://example.com now".to_string()));
+        assert_eq!(f.apply(ID), Ok(ID.to_string()));
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        let f = filter(Rules::default());
+        assert_eq!(f.apply("  a   b \t c  \n  d  e "), Ok("a b c\nd e".to_string()));
+        assert_eq!(f.apply("a https://example.com b"), Ok("a b".to_string()));
+    }
+
+    #[test]
+    fn message_empty_after_stripping_is_rejected() {
+        let f = filter(Rules::default());
+        assert_eq!(f.apply(""), Err("empty"));
+        assert_eq!(f.apply(" \n\t "), Err("empty"));
+        assert_eq!(f.apply(&format!(" https://example.com {} ", ID)), Err("empty"));
+    }
+
+    #[test]
+    fn length_is_limited_in_characters() {
+        let f = filter(Rules { max_length: 5, ..Rules::default() });
+        assert_eq!(f.apply("abcde"), Ok("abcde".to_string()));
+        assert_eq!(f.apply("abcdef"), Err("too long"));
+        assert_eq!(f.apply("zażół"), Ok("zażół".to_string()));
+        assert_eq!(f.apply("zażółć"), Err("too long"));
+        // length is checked after stripping
+        assert_eq!(f.apply("ab   https://example.com"), Ok("ab".to_string()));
+
+        let f = filter(Rules { max_length: 0, ..Rules::default() });
+        let long: String = ::std::iter::repeat('a').take(10000).collect();
+        assert_eq!(f.apply(&long), Ok(long.clone()));
+    }
+
+    #[test]
+    fn blocked_words_match_whole_words() {
+        let f = filter(Rules { words: vec!["Sleek".to_string()], ..Rules::default() });
+        assert_eq!(f.apply("so sleek!"), Err("blocked word"));
+        assert_eq!(f.apply("SLEEK."), Err("blocked word"));
+        assert_eq!(f.apply("(sleek)"), Err("blocked word"));
+        assert_eq!(f.apply("sleeker"), Ok("sleeker".to_string()));
+        assert_eq!(f.apply("unsleek"), Ok("unsleek".to_string()));
+    }
+
+    #[test]
+    fn blocked_patterns_match_anywhere() {
+        let f = filter(Rules { patterns: vec![r"(?i)buy\s+now".to_string()],
+                               ..Rules::default() });
+        assert_eq!(f.apply("you should BUY  now"), Err("blocked pattern"));
+        assert_eq!(f.apply("buy it now"), Ok("buy it now".to_string()));
+        assert!(Filter::new(&Rules { patterns: vec!["(".to_string()],
+                                     ..Rules::default() }).is_err());
+    }
+}
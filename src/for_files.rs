@@ -21,7 +21,9 @@
     For getting stuff from files
 */
 use std::io::prelude::*;
-use std::fs::File;
+use std::io;
+use std::fs::{self, File};
+use std::path::Path;
 
 
 /*
@@ -30,94 +32,189 @@ use std::fs::File;
 extern crate markov;
 use markov::Chain;
 
+extern crate rustc_serialize;
+use self::rustc_serialize::Decodable;
+use self::rustc_serialize::json::{self, Json};
+
+use corpus;
+use markov_txt;
+use stats;
+
+
+/**
+    Feed markov chain with messages from a plaintext file, in format
+    described in `markov_txt`.
+
+    Problems found in file are printed along with line numbers. In a case
+    where file can't be read, chain isn't fed.
+*/
+pub fn feed_markov(chain: &mut Chain<String>, file: &str) {
+    let mut content = String::new();
+    if let Err(e) = File::open(file)
+                        .and_then(|mut f| f.read_to_string(&mut content)) {
+        warn!("Error reading {}: {}", file, e);
+        return;
+    }
+
+    let (entries, diagnostics) = markov_txt::parse(&content);
+    for d in &diagnostics {
+        warn!("`{}`, line {}: {}", file, d.line, d.message);
+    }
+
+    for entry in &entries {
+        chain.feed_str(&entry.text);
+    }
+    info!("Fed chain with {} messages from `{}`.", entries.len(), file);
+}
+
+
+/**
+    Make an empty chain of given order.
+*/
+pub fn new_chain(order: usize) -> Chain<String> {
+    Chain::for_strings().order(order)
+}
+
 
 /*
-    Function to read file and return vector of strings, each of them
-    corresponding to a line from a file.
+    Order of chains saved before it became configurable, which don't have
+    it saved along with them.
+*/
+const LEGACY_ORDER: usize = 1;
 
-    In a case where there is no file, return early.
+
+/*
+    Load chain saved by `save_chain()`, along with its order, and whether
+    it's rebuildable.
 */
-fn vec_strings(file: &str) -> Result<Vec<String>, ()> {
-    let mut file = match File::open(file) {
-        Ok(f) => f,
-        Err(e) => {
-            println!("Error opening {}: {}", file, e);
-            return Err(())
+fn load_chain(f: &str) -> Result<(Chain<String>, usize, bool), String> {
+    let mut content = String::new();
+    try!(File::open(f).and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("{}", e)));
+
+    let mut json = try!(Json::from_str(&content).map_err(|e| format!("{}", e)));
+    let saved_order = json.find("order").and_then(|o| o.as_u64());
+    let order = match saved_order {
+        Some(o) => o as usize,
+        None => match json {
+            Json::Object(ref mut chain) => {
+                chain.insert("order".to_string(), Json::U64(LEGACY_ORDER as u64));
+                LEGACY_ORDER
+            },
+            _ => return Err("chain isn't a JSON object".to_string()),
         },
     };
 
-    let mut content = String::new();
-    file.read_to_string(&mut content).unwrap();
+    let rebuildable = json.find("rebuildable").and_then(|r| r.as_boolean())
+        .unwrap_or(false);
 
-    Ok(content.lines().map(|l| l.to_string()).collect())
+    Decodable::decode(&mut json::Decoder::new(json))
+        .map(|chain| (chain, order, rebuildable))
+        .map_err(|e| format!("{}", e))
 }
 
+
 /**
-    Feed markov chain with strings from a file.
+    Function to make chain - either load it from a file, or from the newest
+    of its backups that can be loaded, or, if that will fail for some
+    reason, make an empty chain of given `order` and feed it with contents
+    of plaintext file `txt`.
+
+    In case where loaded chain has different order than `order`, it's
+    still used, since changing order of a chain requires rebuilding it
+    with `rebuild_chain()`.
 
-    In a case where file can't be used, an empty chain will be returned.
+    Along with chain, returns whether it's rebuildable, i.e. whether
+    everything in it is in `txt` and the corpus, so that rebuilding it
+    wouldn't lose anything. Chain made from `txt` is; loaded chain is only
+    if it was saved as such.
 */
-pub fn feed_markov(chain: &mut Chain<String>) {
-    /*
-        Get vector of strings from the file `markov.txt`. In a case where
-        there is no file supplied, return early without feeding chain.
-    */
-    let vec_of_strings: Vec<String> = match vec_strings("markov.txt") {
-        Ok(v) => v,
-        Err(_) => return,
-    };
+pub fn make_chain(file: &str, txt: &str, order: usize, backups: usize)
+    -> (Chain<String>, bool)
+{
+    match load_with_backups(file, backups, load_chain) {
+        Ok((chain, loaded, rebuildable)) => {
+            if loaded != order {
+                error!("Chain in `{}` has order {}, but order {} is \
+                        configured. Keeping order {}. To change it, run Lee \
+                        with `--rebuild-chain`, which rebuilds chain from \
+                        plaintext file and corpus.", file, loaded, order,
+                        loaded);
+            }
+            if !rebuildable {
+                warn!("Chain in `{}` may have messages learned before the \
+                       corpus was kept, thus rebuilding it could lose them.",
+                      file);
+            }
+            (chain, rebuildable)
+        },
+        Err(e) => {
+            warn!("Error loading `{}`: {}", file, e);
+            let mut chain = new_chain(order);
+            // try to feed it from a plaintext file
+            feed_markov(&mut chain, txt);
+            (chain, true)
+        },
+    }
+}
 
-    /*
-        Initialize string to feed markov
-    */
-    let mut string: String = String::new();
 
-    for line in vec_of_strings {
-        if line.starts_with("//") || line.starts_with("/*") || line == "" {
-            // just skip this line
-            continue;
-        } else if line.ends_with("\\") {
-            // push it to string
-            string.push_str(&line);
-            // check for length after size increased, and before it gets
-            // truncated ↓
-            let string_len = string.len();
-
-            // truncate not needed `\\`
-            string.truncate(string_len - 2);
-
-            // since \n is not treated as char, and cant be `.push(char)`
-            // to string.. make it a &str, and `.push_str()` it
-            string.push_str("\n");
-        } else {
-            // ↓ push to string
-            string.push_str(&line);
-
-            // feed markov with it
-            chain.feed_str(&string);
-
-            // ...aaaand clear string
-            string.clear();
-        }
+/**
+    Count messages in plaintext file `txt` that match `pattern`.
+*/
+pub fn count_txt_matches(txt: &str, pattern: &corpus::Pattern) -> usize {
+    let mut content = String::new();
+    match File::open(txt).and_then(|mut f| f.read_to_string(&mut content)) {
+        Ok(_) => markov_txt::parse(&content).0.iter()
+                    .filter(|e| pattern.matches(&e.text)).count(),
+        Err(_) => 0,
     }
 }
 
 
 /**
-    Function to make chain - either load it from a file, or, if that will
-    fail for some reason, make an empty chain and feed it with contents of
-    plaintext file.
+    Make a new chain of given `order`, and feed it with contents of
+    plaintext file `txt` and with corpus `entries`.
 */
-pub fn make_chain(file: &str) -> Chain<String> {
-    match Chain::load_utf8(file) {
-        Ok(data) => data,
-        Err(e) => {
-            println!("Error loading `{}`: {}", file, e);
-            let mut chain = Chain::for_strings();
-            // try to feed it from a plaintext file
-            feed_markov(&mut chain);
-            chain
+pub fn rebuild_chain(txt: &str, entries: &[corpus::Entry], order: usize)
+    -> Chain<String>
+{
+    let mut chain = new_chain(order);
+    feed_markov(&mut chain, txt);
+    for entry in entries {
+        chain.feed_str(&entry.text);
+    }
+    info!("Rebuilt chain of order {} from `{}` and {} messages from corpus.",
+          order, txt, entries.len());
+    chain
+}
+
+
+/**
+    Save markov chain to a file, in the same format as `Chain::save_utf8()`
+    does, but with `write_save()`, so that crash during save wouldn't
+    destroy the chain.
+
+    Since chain has to be encoded for saving anyway, its size is counted
+    too, and returned for stats.
+
+    Rebuildable chain is marked as such, so that it would be known after
+    loading it.
+*/
+pub fn save_chain(chain: &Chain<String>, file: &str, backups: usize,
+                  rebuildable: bool) -> Result<Option<(usize, usize)>, String>
+{
+    match json::encode(chain) {
+        Ok(data) => {
+            let size = stats::chain_size(&data);
+            let data = if rebuildable && data.starts_with("{") {
+                format!("{{\"rebuildable\":true,{}", &data[1..])
+            } else {
+                data
+            };
+            write_save(file, data.into_bytes(), backups).map(|_| size)
         },
+        Err(e) => Err(format!("{}", e)),
     }
 }
 
@@ -125,14 +222,20 @@ pub fn make_chain(file: &str) -> Chain<String> {
 /**
     Function to load save file from `save.tox` file.
 
-    In case where it can't be opened, return an error, so that it could
-    be printed, and Tox instance could be initialized without it.
+    In case where it can't be opened or read, or is empty, return an error,
+    so that it could be printed, and Tox instance could be initialized
+    without it.
 */
 pub fn load_save(f: &str) -> Result<Vec<u8>, String> {
     match File::open(f) {
         Ok(mut file) => {
             let mut res: Vec<u8> = Vec::new();
-            drop(file.read_to_end(&mut res));
+            if let Err(e) = file.read_to_end(&mut res) {
+                return Err(format!("{}", e));
+            }
+            if res.is_empty() {
+                return Err("file is empty".to_string());
+            }
             Ok(res)
         },
 
@@ -143,21 +246,196 @@ pub fn load_save(f: &str) -> Result<Vec<u8>, String> {
 }
 
 
+/*
+    Name of `n`th backup of a file, `1` being the newest.
+*/
+fn backup_name(f: &str, n: usize) -> String {
+    format!("{}.{}", f, n)
+}
+
+
+/**
+    Load file with `load`, and in case where that fails, try to load its
+    backups, from the newest to the oldest one.
+
+    Every failure is printed. If all of them fail, return the error from
+    loading the file itself.
+*/
+pub fn load_with_backups<T, F>(f: &str, backups: usize, load: F)
+    -> Result<T, String>
+    where F: Fn(&str) -> Result<T, String>
+{
+    let err = match load(f) {
+        Ok(t) => return Ok(t),
+        Err(e) => e,
+    };
+
+    for n in 1..backups + 1 {
+        let backup = backup_name(f, n);
+        if fs::metadata(&backup).is_err() {
+            continue;
+        }
+
+        warn!("Error loading `{}`: {}, trying `{}`", f, err, backup);
+        match load(&backup) {
+            Ok(t) => {
+                info!("Loaded backup `{}`", backup);
+                return Ok(t);
+            },
+            Err(e) => warn!("Error loading `{}`: {}", backup, e),
+        }
+    }
+
+    Err(err)
+}
+
+
+/**
+    Remove up to `backups` backups of a file, e.g. because they have
+    something that shouldn't be kept anymore. Missing backups are skipped.
+*/
+pub fn remove_backups(f: &str, backups: usize) -> Result<(), String> {
+    for n in 1..backups + 1 {
+        let backup = backup_name(f, n);
+        if fs::metadata(&backup).is_ok() {
+            try!(fs::remove_file(&backup)
+                    .map_err(|e| format!("can't remove `{}`: {}", backup, e)));
+        }
+    }
+    Ok(())
+}
+
+
+/*
+    Shift backups of a file by one, dropping the oldest one, and make a copy
+    of the file as the newest backup.
+
+    Missing backups are skipped.
+*/
+fn rotate_backups(f: &str, backups: usize) -> io::Result<()> {
+    if backups == 0 || fs::metadata(f).is_err() {
+        return Ok(());
+    }
+
+    for n in (1..backups).rev() {
+        let older = backup_name(f, n);
+        if fs::metadata(&older).is_ok() {
+            try!(fs::rename(&older, backup_name(f, n + 1)));
+        }
+    }
+    try!(fs::copy(f, backup_name(f, 1)));
+    Ok(())
+}
+
+
+/*
+    Write data to a temporary file next to `f`, and make sure that it's
+    actually on disk.
+*/
+fn write_tmp(tmp: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = try!(File::create(tmp));
+    try!(file.write_all(data));
+    file.sync_all()
+}
+
+
 /**
     Function to write save file to storage.
 
+    Data is first written to a temporary file and synced, and only then
+    the temporary file replaces the old one, so that crash during a write
+    wouldn't leave a truncated file behind. Before replacing, previous
+    file is copied to a backup, with up to `backups` of them, so that the
+    file itself is never missing.
+
     In case where it can't be written to, return an error, so that it could
     be printed.
 */
-pub fn write_save(f: &str, data: Vec<u8>) -> Result<(), String> {
-    match File::create(f) {
-        Ok(mut file) => {
-            drop(file.write(&data));
-            Ok(())
-        },
+pub fn write_save(f: &str, data: Vec<u8>, backups: usize) -> Result<(), String> {
+    let tmp = format!("{}.tmp", f);
+    if let Err(e) = write_tmp(&tmp, &data) {
+        drop(fs::remove_file(&tmp));
+        return Err(format!("{}", e));
+    }
 
-        Err(e) => {
-            Err(format!("{}", e))
-        },
+    if let Err(e) = rotate_backups(f, backups) {
+        warn!("Failed to make backup of `{}`: {}", f, e);
+    }
+
+    if let Err(e) = fs::rename(&tmp, f) {
+        return Err(format!("{}", e));
+    }
+
+    /*
+        Sync directory, so that rename itself would be on disk too.
+    */
+    let dir = match Path::new(f).parent() {
+        Some(d) if d != Path::new("") => d.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    if let Ok(d) = File::open(dir) {
+        drop(d.sync_all());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+
+    use markov::Chain;
+
+    use super::rustc_serialize::json::{self, Json};
+
+    #[test]
+    fn chain_without_order_has_legacy_order() {
+        let mut chain = new_chain(1);
+        chain.feed_str("hello there friend");
+        let mut saved = Json::from_str(&json::encode(&chain).unwrap()).unwrap();
+        if let Json::Object(ref mut obj) = saved {
+            assert!(obj.remove("order").is_some());
+        }
+
+        let dir = env::temp_dir().join("lee-test-legacy-chain");
+        drop(fs::remove_dir_all(&dir));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("markov.json").to_string_lossy().into_owned();
+        File::create(&file).unwrap()
+            .write_all(saved.to_string().as_bytes()).unwrap();
+
+        let (loaded, order, rebuildable): (Chain<String>, usize, bool) =
+            load_chain(&file).unwrap();
+        assert_eq!(order, 1);
+        assert!(!rebuildable);
+        assert_eq!(loaded.generate_str(), "hello there friend");
+    }
+
+    #[test]
+    fn previous_save_is_kept_as_backup() {
+        let dir = env::temp_dir().join("lee-test-backups");
+        drop(fs::remove_dir_all(&dir));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data").to_string_lossy().into_owned();
+        let read = |f: &str| -> Result<String, String> {
+            let mut content = String::new();
+            try!(File::open(f).and_then(|mut f| f.read_to_string(&mut content))
+                    .map_err(|e| format!("{}", e)));
+            Ok(content)
+        };
+
+        for data in &["one", "two", "three"] {
+            write_save(&file, data.as_bytes().to_vec(), 1).unwrap();
+        }
+        assert_eq!(read(&file).unwrap(), "three");
+        assert_eq!(read(&backup_name(&file, 1)).unwrap(), "two");
+        assert!(fs::metadata(backup_name(&file, 2)).is_err());
+
+        fs::remove_file(&file).unwrap();
+        assert_eq!(load_with_backups(&file, 1, &read).unwrap(), "two");
     }
 }
@@ -0,0 +1,112 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Deciding which friend requests should be accepted.
+*/
+
+use rstox::core::PublicKey;
+
+
+/**
+    Policy of accepting friend requests.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Policy {
+    /**
+        Accept every friend request.
+    */
+    AcceptAll,
+
+    /**
+        Accept only requests from public keys on the list.
+    */
+    Allowlist(Vec<String>),
+
+    /**
+        Accept requests from everyone except public keys on the list.
+    */
+    Denylist(Vec<String>),
+
+    /**
+        Accept only requests which message contains passphrase.
+    */
+    Passphrase(String),
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::AcceptAll
+    }
+}
+
+
+impl Policy {
+    /**
+        Check whether friend request from `pk` with message `msg` should be
+        accepted.
+
+        If it shouldn't, return reason why, so that it could be printed.
+    */
+    pub fn check(&self, pk: &PublicKey, msg: &str) -> Result<(), String> {
+        let pk = format!("{}", pk).to_uppercase();
+        match *self {
+            Policy::AcceptAll => Ok(()),
+            Policy::Allowlist(ref keys) => {
+                if keys.contains(&pk) {
+                    Ok(())
+                } else {
+                    Err("public key is not on allowlist".to_string())
+                }
+            },
+            Policy::Denylist(ref keys) => {
+                if keys.contains(&pk) {
+                    Err("public key is on denylist".to_string())
+                } else {
+                    Ok(())
+                }
+            },
+            Policy::Passphrase(ref pass) => {
+                if msg.contains(&**pass) {
+                    Ok(())
+                } else {
+                    Err("message doesn't contain passphrase".to_string())
+                }
+            },
+        }
+    }
+}
+
+
+/**
+    Normalize public key, given either as a public key, or as a whole Tox
+    ID, to uppercase public key.
+
+    Returns `None` if it's neither.
+*/
+pub fn normalize_key(key: &str) -> Option<String> {
+    let key = key.trim();
+    if key.len() != 64 && key.len() != 76 {
+        return None;
+    }
+    if !key.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+    Some(key[..64].to_uppercase())
+}
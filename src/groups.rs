@@ -0,0 +1,121 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    State of Lee in a single groupchat.
+
+    Every groupchat has its own, so that e.g. `.stahp` in one groupchat
+    wouldn't silence Lee in all of them.
+*/
+
+use chrono::UTC;
+
+
+#[derive(Clone, Debug)]
+pub struct Group {
+    /**
+        Option to allow Lee talk at $random_interval, it does not affect
+        Lee's response when triggered (highlighted).
+
+        Can be altered by users using commands:
+         - `.stahp` – will make Lee stop speaking randomly
+         - `.talk`  – will make Lee resume speaking randomly
+
+        Default value should be `true`.
+    */
+    pub speak: bool,
+
+    /**
+        `trigger` is used to launch Lee's talk when something will trigger
+        it, by mentioning its name. Answer shouldn't be instantaneous, which
+        will make Lee more human.
+
+        By default should be `false`, and after countdown was down to 0, it
+        should be restored to `false`.
+    */
+    pub trigger: bool,
+
+    /**
+        Time when Lee should respond to trigger, as UNIX time in i64.
+
+        Random amount of seconds should be added to time of a trigger, so
+        that time of Lee's response would be more human-like, rather than
+        instantaneous.
+    */
+    pub trigger_time: i64,
+
+    /**
+        Message that triggered Lee, from which reply should be seeded.
+    */
+    pub trigger_message: String,
+
+    /**
+        Time since Lee last tried to speak randomly in this groupchat.
+    */
+    pub last_time: i64,
+}
+
+
+impl Group {
+    pub fn new() -> Group {
+        Group {
+            speak: true,
+            trigger: false,
+            trigger_time: UTC::now().timestamp(),
+            trigger_message: String::new(),
+            last_time: UTC::now().timestamp(),
+        }
+    }
+
+    /**
+        Schedule response to a trigger, `wait` seconds from now. If Lee was
+        already triggered, response is seeded from the newest message.
+    */
+    pub fn set_trigger(&mut self, wait: i64, message: &str) {
+        self.trigger = true;
+        self.trigger_time = UTC::now().timestamp() + wait;
+        self.trigger_message = message.to_string();
+    }
+
+    /**
+        Check whether it's time to respond to a trigger, and if it is,
+        reset trigger and return message that triggered Lee.
+    */
+    pub fn take_trigger(&mut self, cur_time: i64) -> Option<String> {
+        if self.trigger && cur_time >= self.trigger_time {
+            self.trigger = false;
+            return Some(::std::mem::replace(&mut self.trigger_message,
+                                            String::new()));
+        }
+        None
+    }
+
+    /**
+        Check whether it's time to try to speak randomly, i.e. whether
+        speaking is allowed, and `interval` seconds have passed since the
+        last try. If it is, start counting time again.
+    */
+    pub fn take_talk_turn(&mut self, cur_time: i64, interval: i64) -> bool {
+        if self.speak && self.last_time + interval < cur_time {
+            self.last_time = cur_time;
+            return true;
+        }
+        false
+    }
+}
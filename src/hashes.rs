@@ -0,0 +1,131 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Hashes of messages that were already fed to markov chain, so that the
+    same message wouldn't be learned twice.
+
+    Hashes are kept in a set for lookup, and in a queue in order in which
+    they were added, so that when there's too many of them, the oldest ones
+    could be evicted.
+*/
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher, SipHasher};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+
+
+pub struct Hashes {
+    set: HashSet<u64>,
+    order: VecDeque<u64>,
+
+    /**
+        Max number of hashes that are kept.
+    */
+    cap: usize,
+}
+
+
+/**
+    Hash a message.
+
+    `SipHasher` with default keys is used, so that hashes of the same
+    message would be the same after restart.
+*/
+pub fn hash_message(message: &str) -> u64 {
+    let mut hasher = SipHasher::default();
+    message.hash(&mut hasher);
+    hasher.finish()
+}
+
+
+impl Hashes {
+    pub fn new(cap: usize) -> Hashes {
+        Hashes {
+            set: HashSet::new(),
+            order: VecDeque::new(),
+            cap: cap,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    /**
+        Add hash of a message.
+
+        Returns `false` if it was already there, `true` otherwise.
+    */
+    pub fn insert(&mut self, message: &str) -> bool {
+        self.insert_hash(hash_message(message))
+    }
+
+    fn insert_hash(&mut self, hash: u64) -> bool {
+        if !self.set.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+
+        while self.order.len() > self.cap {
+            if let Some(old) = self.order.pop_front() {
+                self.set.remove(&old);
+            }
+        }
+        true
+    }
+
+    /**
+        Load hashes from a file, with one hash per line, oldest first.
+
+        In case where there are more hashes than `cap`, only the newest
+        ones are kept. Lines that aren't valid hashes are skipped.
+    */
+    pub fn load(file: &str, cap: usize) -> Result<Hashes, String> {
+        let file = match File::open(file) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("{}", e)),
+        };
+
+        let mut hashes = Hashes::new(cap);
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => return Err(format!("{}", e)),
+            };
+            if let Ok(hash) = u64::from_str_radix(line.trim(), 16) {
+                hashes.insert_hash(hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /**
+        Serialize hashes in format that `load()` reads.
+    */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.order.len() * 17);
+        for hash in &self.order {
+            drop(writeln!(data, "{:016x}", hash));
+        }
+        data
+    }
+}
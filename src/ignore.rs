@@ -0,0 +1,135 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    List of ignored peers, e.g. other bots or trolls. Lee neither learns
+    from ignored peers, nor reacts to them in any way.
+
+    Peers are identified by their public keys, and list is saved as a
+    plaintext file, with one uppercase public key per line.
+*/
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use rstox::core::PublicKey;
+
+use for_files;
+use friend_requests::normalize_key;
+
+
+pub struct IgnoreList {
+    file: String,
+    backups: usize,
+    keys: BTreeSet<String>,
+}
+
+
+impl IgnoreList {
+    /**
+        Load list from a file, or from the newest of its backups that can
+        be loaded. Missing file means empty list, and lines that aren't
+        public keys are skipped with a warning.
+    */
+    pub fn load(file: &str, backups: usize) -> IgnoreList {
+        let keys = match for_files::load_with_backups(file, backups, read_keys) {
+            Ok(keys) => {
+                info!("Loaded {} ignored peers from `{}`.", keys.len(), file);
+                keys
+            },
+            Err(e) => {
+                if fs::metadata(file).is_ok() {
+                    warn!("Error reading `{}`: {}", file, e);
+                }
+                BTreeSet::new()
+            },
+        };
+
+        IgnoreList {
+            file: file.to_string(),
+            backups: backups,
+            keys: keys,
+        }
+    }
+
+    pub fn is_ignored(&self, pk: &PublicKey) -> bool {
+        self.keys.contains(&format!("{}", pk).to_uppercase())
+    }
+
+    /**
+        Uppercase public keys of ignored peers.
+    */
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.iter().cloned().collect()
+    }
+
+    /**
+        Add uppercase public key to the list, and save it. Returns `false`
+        if it was already there.
+    */
+    pub fn add(&mut self, key: &str) -> Result<bool, String> {
+        if !self.keys.insert(key.to_string()) {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    /**
+        Remove uppercase public key from the list, and save it. Returns
+        `false` if it wasn't there.
+    */
+    pub fn remove(&mut self, key: &str) -> Result<bool, String> {
+        if !self.keys.remove(key) {
+            return Ok(false);
+        }
+        self.save().map(|_| true)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let mut data = vec![];
+        for key in &self.keys {
+            drop(writeln!(data, "{}", key));
+        }
+        for_files::write_save(&self.file, data, self.backups)
+    }
+}
+
+
+/*
+    Read public keys from a file, one per line.
+*/
+fn read_keys(file: &str) -> Result<BTreeSet<String>, String> {
+    let f = try!(File::open(file).map_err(|e| format!("{}", e)));
+
+    let mut keys = BTreeSet::new();
+    for (n, line) in BufReader::new(f).lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("{}", e)));
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match normalize_key(&line) {
+            Some(key) => { keys.insert(key); },
+            None => warn!("`{}`, line {}: not a public key, skipping",
+                          file, n + 1),
+        }
+    }
+    Ok(keys)
+}
@@ -0,0 +1,221 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Detecting peers who pretend to be Lee, by using name that looks like
+    Lee's own.
+
+    Names are compared by their "skeletons" – name is lowercased, and
+    characters that look alike, e.g. Cyrillic `е` and Latin `e`, or `I`
+    and `l`, are mapped to the same one, while whitespace, control and
+    zero-width characters, punctuation and combining marks are dropped.
+*/
+
+use std::collections::HashMap;
+
+
+/**
+    Impostor detection configuration.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpostorConfig {
+    pub enabled: bool,
+
+    /**
+        Warning sent when impostor is found. `{name}` is replaced with
+        impostor's name.
+    */
+    pub warning: String,
+
+    /**
+        Minimal time between warnings about the same peer in the same
+        groupchat, in seconds.
+    */
+    pub interval: i64,
+}
+
+impl Default for ImpostorConfig {
+    fn default() -> ImpostorConfig {
+        ImpostorConfig {
+            enabled: true,
+            warning: "{name} is an impostor!".to_string(),
+            interval: 300,
+        }
+    }
+}
+
+
+/*
+    Map character that looks like another one to that one.
+*/
+fn unconfuse(c: char) -> char {
+    match c {
+        // fullwidth forms of ASCII
+        '\u{FF01}'...'\u{FF5E}' =>
+            ::std::char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+
+        'а' | 'α' | '@' => 'a',
+        'в' | 'β' => 'b',
+        'с' | 'ϲ' | '¢' => 'c',
+        'ԁ' => 'd',
+        'е' | 'ё' | 'ε' | 'є' | '3' | '€' => 'e',
+        'һ' => 'h',
+        'i' | 'ı' | 'і' | 'ї' | 'ι' | 'ӏ' | '1' | '|' | '!' => 'l',
+        'ј' => 'j',
+        'κ' | 'к' => 'k',
+        'м' => 'm',
+        'η' | 'п' => 'n',
+        'о' | 'ο' | 'σ' | '0' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' | '5' | '$' => 's',
+        'т' | 'τ' | '7' => 't',
+        'υ' | 'ս' => 'u',
+        'ν' | 'ѵ' => 'v',
+        'ԝ' | 'ω' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'γ' => 'y',
+        'ʐ' => 'z',
+        _ => c,
+    }
+}
+
+
+/**
+    Get skeleton of a name, which is the same for names that look alike.
+*/
+pub fn skeleton(name: &str) -> String {
+    name.chars()
+        .flat_map(|c| c.to_lowercase())
+        .map(unconfuse)
+        .filter(|&c| {
+            let combining = c >= '\u{300}' && c <= '\u{36F}';
+            let zero_width = c >= '\u{200B}' && c <= '\u{200F}' || c == '\u{FEFF}';
+            c.is_alphanumeric() && !combining && !zero_width
+        })
+        .collect()
+}
+
+
+pub struct Detector {
+    pub config: ImpostorConfig,
+
+    /**
+        Time of the last warning, by groupchat and uppercase public key of
+        impostor.
+    */
+    warned: HashMap<(i32, String), i64>,
+}
+
+
+impl Detector {
+    pub fn new(config: ImpostorConfig) -> Detector {
+        Detector {
+            config: config,
+            warned: HashMap::new(),
+        }
+    }
+
+    /**
+        Check whether `name` looks like `own_name`.
+    */
+    pub fn is_impostor(&self, own_name: &str, name: &str) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        let name = skeleton(name);
+        !name.is_empty() && name == skeleton(own_name)
+    }
+
+    /**
+        Get warning about impostor with given `name` and public key `pk`
+        in groupchat `gnum`, unless there already was a warning about it
+        recently.
+    */
+    pub fn warning(&mut self, gnum: i32, pk: &str, name: &str, cur_time: i64)
+        -> Option<String>
+    {
+        let interval = self.config.interval;
+        let expired: Vec<(i32, String)> = self.warned.iter()
+            .filter(|&(_, &t)| t + interval <= cur_time)
+            .map(|(k, _)| k.clone()).collect();
+        for k in expired {
+            self.warned.remove(&k);
+        }
+
+        let key = (gnum, pk.to_string());
+        if self.warned.contains_key(&key) {
+            return None;
+        }
+        self.warned.insert(key, cur_time);
+        Some(self.config.warning.replace("{name}", name))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_that_look_alike_have_the_same_skeleton() {
+        for name in &["Lee", "LEE", "Lee ", " l e e", "Lее", "Ｌｅｅ", "L\u{200B}ee",
+                      "Le\u{301}e", "Iee", "1ee", "L3e", "L.e.e"] {
+            assert_eq!(skeleton(name), "lee", "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn characters_are_unconfused() {
+        assert_eq!(unconfuse('е'), 'e');
+        assert_eq!(unconfuse('ο'), 'o');
+        assert_eq!(unconfuse('Ａ'), 'A');
+        assert_eq!(unconfuse('0'), 'o');
+        assert_eq!(unconfuse('e'), 'e');
+        assert_eq!(unconfuse('ł'), 'ł');
+    }
+
+    #[test]
+    fn impostors_are_found() {
+        let detector = Detector::new(ImpostorConfig::default());
+        for name in &["LEE", "Lee ", "Lее"] {
+            assert!(detector.is_impostor("Lee", name), "{:?}", name);
+        }
+        for name in &["Leo", "Lee2", "Leela", "Alee", "Łee", "", "..."] {
+            assert!(!detector.is_impostor("Lee", name), "{:?}", name);
+        }
+
+        let detector = Detector::new(ImpostorConfig {
+            enabled: false,
+            ..ImpostorConfig::default()
+        });
+        assert!(!detector.is_impostor("Lee", "LEE"));
+    }
+
+    #[test]
+    fn warnings_are_repeated_only_after_interval() {
+        let mut detector = Detector::new(ImpostorConfig::default());
+        let warning = Some("Lее is an impostor!".to_string());
+        assert_eq!(detector.warning(0, "PK", "Lее", 1000), warning);
+        assert_eq!(detector.warning(0, "PK", "Lее", 1299), None);
+        assert_eq!(detector.warning(1, "PK", "Lее", 1299), warning);
+        assert_eq!(detector.warning(0, "PK", "Lее", 1300), warning);
+    }
+}
@@ -0,0 +1,292 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Logger used by Lee.
+
+    Every record has a level, and a target, which by default is a module
+    path, e.g. `lee::bootstrap`. Content of messages received and sent by
+    Lee is logged with `MESSAGES` target, so that it could be filtered out
+    separately from operational logs.
+
+    Records can be written as plain text or as JSON lines, to stdout and/or
+    to a file, which is rotated when it gets too big.
+*/
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+use chrono::UTC;
+
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::Json;
+
+
+/**
+    Target used for logging content of chat messages.
+*/
+pub const MESSAGES: &'static str = "lee::messages";
+
+
+/**
+    Logging configuration.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    /**
+        Filter in form of `level,target=level,...`, e.g.
+        `info,lee::bootstrap=debug,lee::messages=off`.
+    */
+    pub filter: String,
+
+    /**
+        Whether records should be written as JSON lines.
+    */
+    pub json: bool,
+
+    /**
+        Whether records should be written to stdout.
+    */
+    pub stdout: bool,
+
+    /**
+        File to which records should be written, if any.
+    */
+    pub file: Option<String>,
+
+    /**
+        Size in bytes after which log file is rotated.
+    */
+    pub max_size: u64,
+
+    /**
+        Number of rotated log files that are kept.
+    */
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            filter: "info".to_string(),
+            json: false,
+            stdout: true,
+            file: None,
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+
+/**
+    Parsed `LogConfig.filter`.
+*/
+#[derive(Debug)]
+pub struct Filter {
+    default: LogLevelFilter,
+    targets: Vec<(String, LogLevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Filter, String> {
+        let mut parsed = Filter {
+            default: LogLevelFilter::Info,
+            targets: vec![],
+        };
+
+        for part in filter.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let mut split = part.splitn(2, '=');
+            let first = split.next().unwrap_or("");
+            match split.next() {
+                Some(level) => {
+                    let level = try!(parse_level(level));
+                    parsed.targets.push((first.to_string(), level));
+                },
+                None => parsed.default = try!(parse_level(first)),
+            }
+        }
+
+        // the most specific target should be matched first
+        parsed.targets.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(parsed)
+    }
+
+    /**
+        Get max level of records that should be logged for a target.
+    */
+    fn level(&self, target: &str) -> LogLevelFilter {
+        for &(ref t, level) in &self.targets {
+            if target == &t[..] || target.starts_with(&format!("{}::", t)) {
+                return level;
+            }
+        }
+        self.default
+    }
+
+    /**
+        The most verbose level out of all of them.
+    */
+    fn max(&self) -> LogLevelFilter {
+        self.targets.iter().map(|t| t.1).fold(self.default, |a, b| {
+            if a > b { a } else { b }
+        })
+    }
+}
+
+fn parse_level(level: &str) -> Result<LogLevelFilter, String> {
+    level.trim().parse()
+        .map_err(|_| format!("invalid log level {:?}", level))
+}
+
+
+/*
+    Log file, rotated when it exceeds `max_size`: `lee.log` becomes
+    `lee.log.1`, `lee.log.1` becomes `lee.log.2`, and so on, up to
+    `max_files`.
+*/
+struct LogFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn open(path: &str, max_size: u64, max_files: usize) -> Result<LogFile, String> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("can't open log file `{}`: {}", path, e)));
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(LogFile {
+            path: path.to_string(),
+            file: file,
+            size: size,
+            max_size: max_size,
+            max_files: max_files,
+        })
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate();
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+
+    fn rotate(&mut self) {
+        for n in (1..self.max_files).rev() {
+            drop(fs::rename(format!("{}.{}", self.path, n),
+                            format!("{}.{}", self.path, n + 1)));
+        }
+        if self.max_files > 0 {
+            drop(fs::rename(&self.path, format!("{}.1", self.path)));
+        } else {
+            drop(fs::remove_file(&self.path));
+        }
+
+        if let Ok(f) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            self.file = f;
+            self.size = 0;
+        }
+    }
+}
+
+
+struct Logger {
+    filter: Filter,
+    json: bool,
+    stdout: bool,
+    file: Option<Mutex<LogFile>>,
+}
+
+impl Logger {
+    fn format(&self, record: &LogRecord) -> String {
+        let time = UTC::now();
+        if self.json {
+            let mut obj = BTreeMap::new();
+            obj.insert("time".to_string(), Json::String(format!("{}", time)));
+            obj.insert("level".to_string(),
+                       Json::String(format!("{}", record.level())));
+            obj.insert("target".to_string(),
+                       Json::String(record.target().to_string()));
+            obj.insert("message".to_string(),
+                       Json::String(format!("{}", record.args())));
+            format!("{}\n", Json::Object(obj))
+        } else {
+            format!("{} {:<5} {}: {}\n",
+                    time, record.level(), record.target(), record.args())
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(record);
+        if self.stdout {
+            print!("{}", line);
+        }
+        if let Some(ref file) = self.file {
+            if let Ok(mut f) = file.lock() {
+                f.write(&line);
+            }
+        }
+    }
+}
+
+
+/**
+    Set up logging according to config.
+
+    Should be called only once, as early as possible.
+*/
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let filter = try!(Filter::parse(&config.filter));
+    let file = match config.file {
+        Some(ref path) => Some(Mutex::new(
+            try!(LogFile::open(path, config.max_size, config.max_files)))),
+        None => None,
+    };
+
+    let logger = Logger {
+        filter: filter,
+        json: config.json,
+        stdout: config.stdout,
+        file: file,
+    };
+
+    log::set_logger(|max_level| {
+        max_level.set(logger.filter.max());
+        Box::new(logger)
+    }).map_err(|e| format!("{}", e))
+}
@@ -37,21 +37,24 @@ use markov::Chain;
 extern crate chrono;
 use chrono::UTC;
 
+/*
+    For logging
+*/
+#[macro_use]
+extern crate log;
+
 /*
  * For regex purposes
 */
 extern crate regex;
-use regex::Regex;
 
 
 extern crate rand;
 use rand::ThreadRng;
 use rand::Rng;
 
-/*
-    Needed for checking hashes of received messages
-*/
-use std::hash::{Hash, Hasher, SipHasher};
+use std::collections::HashMap;
+use std::fs;
 
 /*
     Lee's own stuff
@@ -59,7 +62,41 @@ use std::hash::{Hash, Hasher, SipHasher};
 // TODO: when other functions will be moved from main.rs, things should be
 //       added here
 mod bootstrap;
+mod chains;
+mod commands;
+mod config;
+mod corpus;
+mod filter;
 mod for_files;
+mod friend_requests;
+mod groups;
+mod hashes;
+mod ignore;
+mod impostor;
+mod logging;
+mod markov_txt;
+mod permissions;
+mod persist;
+mod ratelimit;
+mod reply;
+mod stats;
+mod transport;
+mod triggers;
+
+use chains::GroupChains;
+use commands::{Arg, Call, Command, Parsed, Registry, Scope};
+use config::{Config, ConfigWatcher};
+use corpus::Corpus;
+use filter::Filter;
+use groups::Group;
+use hashes::Hashes;
+use ignore::IgnoreList;
+use impostor::Detector;
+use persist::{Data, Persist};
+use ratelimit::{Action, Limits, Verdict};
+use stats::Stats;
+use transport::{Chat, Transport};
+use triggers::Triggers;
 
 
 
@@ -67,18 +104,23 @@ mod for_files;
     For bot functionality
 */
 //#[derive(Debug)]   // can't be used, since `rand` doesn't want to cooperate
-struct Bot {
+struct Bot<T: Transport> {
     /**
-        Tox struct.
+        Connection to Tox network – either real `Tox` instance, or fake
+        one.
     */
-    tox: Tox,
+    tox: T,
 
     /**
         Bot name.
     */
-    // TODO: load from config file
     name: String,
 
+    /**
+        Configuration, as loaded from config file at startup.
+    */
+    config: Config,
+
     /**
         Markov chain of strings received from groupchat, friends and
         fed from file.
@@ -86,59 +128,84 @@ struct Bot {
     markov: Chain<String>,
 
     /**
-        Vector with hashes of received messages.
+        Whether everything in markov chain is in `markov.txt` and the
+        corpus, i.e. whether it can be rebuilt without losing anything.
+        Chains saved before the corpus was kept aren't.
     */
-    hashes: Vec<u64>,
+    rebuildable: bool,
 
     /**
-        Time since last save.
+        Hashes of messages that were fed to markov chain, saved along with
+        it.
     */
-    last_save: i64,
+    hashes: Hashes,
 
     /**
-        Last group from which message of any kind was received.
+        Chains of groupchats, used if enabled in config.
+    */
+    group_chains: GroupChains,
 
-        This value is being used to decide in which groupchat Lee should
-        speak randomly – since out of all groupchats this was the last one
-        in which activity was observed, it is most likely that there are
-        some people in it able to receive Lee's message.
+    /**
+        Filters of messages that Lee learns and says.
     */
-    last_group: i32,
+    learn_filter: Filter,
+    speak_filter: Filter,
+
+    /**
+        Rate limits of triggers, commands and replies to friends.
+    */
+    limits: Limits,
+
+    /**
+        Peers that Lee doesn't learn from or react to.
+    */
+    ignored: IgnoreList,
+
+    /**
+        Defends honour of Lee, by warning about peers who use its name.
+    */
+    impostors: Detector,
 
     /**
-        Time since Lee last spoken randomly.
+        What makes Lee respond in groupchats.
     */
-    last_time: i64,
+    triggers: Triggers,
 
     /**
-        Option to allow Lee talk ar $random_interval, it does not affect Lee's
-        response when triggered (highlighted).
+        Every message that was fed to markov chain.
+    */
+    corpus: Corpus,
 
-        Can be altered by users using commands:
-         - `.stahp` – will make Lee stop speaking randomly
-         - `.talk`  – will make Lee resume speaking randomly
+    /**
+        How much Lee has learned, and how it's doing.
+    */
+    stats: Stats,
 
-        Defalut value should be `true`.
+    /**
+        Tracks what has changed since last save, and when it should be
+        saved.
     */
-    speak: bool,
+    persist: Persist,
 
     /**
-        `trigger` is used to launch Lee's talk when something will trigger
-        it, by mentioning its name. Answer shouldn't be instantaneous, which
-        will make Lee more human.
+        Last group from which message of any kind was received.
 
-        By default should be `false`, and after countdown was down to 0, it
-        should be restored to `false`.
+        This value is being used to decide to which groupchat friends should
+        be invited – since out of all groupchats this was the last one in
+        which activity was observed, it is most likely that there are some
+        people in it.
     */
-    trigger: bool,
+    last_group: i32,
 
     /**
-        Time when trigger happened, as UNIX time in i64.
+        State of Lee in each groupchat that it's in, by group number.
+    */
+    groups: HashMap<i32, Group>,
 
-        Seconds should be added to this value, so that time of Lee's response
-        for trigger would be more human-like, rather than instantaneous.
+    /**
+        Commands that Lee understands.
     */
-    trigger_time: i64,
+    commands: Registry<Bot<T>>,
 
     /**
         Cached RNG, apparently it helps with RNG's performance when it's used
@@ -148,56 +215,485 @@ struct Bot {
 }
 
 
-impl Bot {
+impl<T: Transport> Bot<T> {
     /**
         Create new `Bot` struct.
 
-        Takes data for toxcore's instance to load.
+        Takes transport that should be used, config, markov chain and
+        whether the chain is rebuildable.
     */
-    fn new(data: Option<Vec<u8>>) -> Bot {
+    fn new(tox: T, config: Config, markov: Chain<String>, rebuildable: bool)
+        -> Bot<T>
+    {
+        let chain_size = stats::measure(&markov);
         Bot {
-            tox: Tox::new(ToxOptions::new(), data.as_ref()
-                                            .map(|x| &**x)).unwrap(),
-
-            name: "Lee".to_string(),
-            markov: for_files::make_chain("markov.json"),
-            hashes: vec![],
-            last_save: UTC::now().timestamp(),
+            tox: tox,
+            name: config.name.clone(),
+            markov: markov,
+            rebuildable: rebuildable,
+            hashes: load_hashes(&config),
+            learn_filter: Filter::new(&config.learn_filter).unwrap(),
+            speak_filter: Filter::new(&config.speak_filter).unwrap(),
+            limits: Limits::new(config.rate_limits.clone()),
+            ignored: IgnoreList::load(&config.ignore_file, config.backups),
+            impostors: Detector::new(config.impostors.clone()),
+            // already validated when config was loaded
+            triggers: Triggers::new(&config.name, config.triggers.clone()).unwrap(),
+            group_chains: GroupChains::new(&config.group_chains_dir,
+                                           config.chain_order, config.backups),
+            corpus: Corpus::new(&config.corpus_file),
+            stats: Stats::new(UTC::now().timestamp(),
+                              corpus::count(&config.corpus_file), chain_size),
+            persist: Persist::new(config.save_debounce, config.save_interval),
+            commands: make_commands(&config.command_prefix),
+            config: config,
             last_group: 0,
-            last_time: UTC::now().timestamp(),
-            speak: true,
-            trigger: false,
-            trigger_time: UTC::now().timestamp(),
+            groups: HashMap::new(),
             random: rand::thread_rng(),
         }
     }
 
     /**
-        Check whether hash of a string exists.
+        Get state of groupchat, creating it if Lee didn't know about the
+        groupchat yet.
+    */
+    fn group(&mut self, gnum: i32) -> &mut Group {
+        self.groups.entry(gnum).or_insert_with(Group::new)
+    }
+
+    /**
+        Let Lee speak in groupchats where it was triggered, provided that
+        it waited required amount of time, and in groupchats where it's
+        allowed to speak on its own, every `talk_interval`, with small
+        chance.
+    */
+    fn talk_in_groups(&mut self) {
+        let cur_time = UTC::now().timestamp();
+        let mut talk_in = vec![];
+        for (gnum, group) in self.groups.iter_mut() {
+            if let Some(trigger) = group.take_trigger(cur_time) {
+                talk_in.push((*gnum, Some(trigger)));
+            } else if group.take_talk_turn(cur_time, self.config.talk_interval)
+                      && self.config.talk_chance > self.random.gen::<f64>() {
+                talk_in.push((*gnum, None));
+            }
+        }
+
+        for (gnum, trigger) in talk_in {
+            let message = match trigger {
+                Some(trigger) => self.generate(Chat::Group(gnum), Some(&trigger[..])),
+                None => self.generate(Chat::Group(gnum), None),
+            };
+            if let Some(message) = message {
+                drop(self.tox.send_group_message(gnum, &message));
+            }
+        }
+    }
+
+    /**
+        Get key of chain of groupchat, if chains of groupchats are enabled,
+        and groupchat has a title.
+    */
+    fn group_chain_key(&self, gnum: i32) -> Option<String> {
+        if !self.config.group_chains {
+            return None;
+        }
+        match self.tox.group_title(gnum) {
+            Some(ref title) if !title.is_empty() => Some(chains::group_key(title)),
+            _ => None,
+        }
+    }
+
+    /**
+        Get key of chain that should be used for talking in `chat`, or
+        `None` if the global one should be used – either because chain of
+        groupchat hasn't learned enough yet, or, with `group_chain_blend`
+        chance, just for variety.
+    */
+    fn local_chain(&mut self, chat: Chat) -> Option<String> {
+        let key = match chat {
+            Chat::Group(gnum) => match self.group_chain_key(gnum) {
+                Some(k) => k,
+                None => return None,
+            },
+            Chat::Friend(_) => return None,
+        };
+
+        if self.group_chains.get_mut(&key).learned < self.config.group_chain_min
+            || self.config.group_chain_blend > self.random.gen::<f64>() {
+            return None;
+        }
+        Some(key)
+    }
+
+    /**
+        Generate message for `chat`, seeded from `trigger` if there is one.
+
+        Message has to pass speak filter. If after few tries there's still
+        nothing that would, return `None` – Lee should rather stay silent.
+    */
+    fn generate(&mut self, chat: Chat, trigger: Option<&str>) -> Option<String> {
+        for _ in 0..GENERATE_TRIES {
+            let key = self.local_chain(chat);
+            let message = {
+                let group_chains = &self.group_chains;
+                let chain = match key.and_then(|k| group_chains.get(&k)) {
+                    Some(group) => &group.chain,
+                    None => &self.markov,
+                };
+                match trigger {
+                    Some(trigger) => reply::reply_to(chain, trigger,
+                                                     self.triggers.names(),
+                                                     &mut self.random),
+                    None => chain.generate_str(),
+                }
+            };
+
+            match self.speak_filter.apply(&message) {
+                Ok(m) => return Some(m),
+                Err(reason) => debug!("Not saying generated message: {}",
+                                      reason),
+            }
+        }
+        None
+    }
+
+    /**
+        Add string to markov chain if wasn't already added, and to chain
+        of groupchat from which it came, if there is one.
+
+        Message has to pass learn filter first, and what's learned is the
+        filtered message.
+    */
+    fn add_to_markov(&mut self, message: &str, chat: Chat, pk: &PublicKey) {
+        let message = match self.learn_filter.apply(message) {
+            Ok(m) => m,
+            Err(reason) => {
+                debug!("Not learning message: {}", reason);
+                return;
+            },
+        };
+        let message = &message[..];
+
+        if self.hashes.insert(message) {
+            self.markov.feed_str(message);
+            if let Chat::Group(gnum) = chat {
+                if let Some(key) = self.group_chain_key(gnum) {
+                    self.group_chains.feed(&key, message);
+                }
+            }
+            self.stats.learned += 1;
+            let source = match chat {
+                Chat::Friend(fnum) => corpus::Source::Friend(fnum),
+                Chat::Group(gnum) => corpus::Source::Group(gnum,
+                    self.tox.group_title(gnum).and_then(|t| {
+                        if t.is_empty() { None } else { Some(t) }
+                    })),
+            };
+            if let Err(e) = self.corpus.append(message, source, Some(pk)) {
+                error!("Failed to append to corpus `{}`: {}",
+                       self.corpus.path, e);
+            }
+            self.persist.mark(Data::Chain, UTC::now().timestamp());
+        }
+    }
+
+
+    /**
+        Forget messages matching `pattern`: rebuild chains without them,
+        save the chains, and only then remove messages from the corpus – so
+        that a failure on the way wouldn't leave chains with messages that
+        corpus no longer has.
+
+        Backups of the corpus and of chains still have forgotten messages,
+        thus they're removed.
 
-        If it does, return early `None`.
+        Refused if the chain isn't rebuildable, since rebuilding it would
+        forget a lot more than asked for.
 
-        If it doesn't, add it to existing ones and return string.
+        Returns number of forgotten messages, and what rebuilding did, if
+        anything was forgotten.
     */
-    fn check_hash(&mut self, message: String) -> Option<String> {
-        let mut hasher = SipHasher::default();
-        message.hash(&mut hasher);
-        let hashed = hasher.finish();
-        for h in &self.hashes {
-            if h == &hashed {
-                return None;
+    fn forget(&mut self, pattern: &corpus::Pattern)
+        -> Result<(usize, Option<String>), String>
+    {
+        if !self.rebuildable {
+            return Err(self.not_rebuildable());
+        }
+        if fs::metadata(&self.config.corpus_file).is_err() {
+            return Ok((0, None));
+        }
+
+        let entries = try!(self.load_corpus());
+        let total = entries.len();
+        let kept: Vec<corpus::Entry> = entries.into_iter()
+            .filter(|e| !pattern.matches(&e.text)).collect();
+        let removed = total - kept.len();
+        if removed == 0 {
+            return Ok((0, None));
+        }
+
+        let rebuilt = self.rebuild_from(&kept);
+        try!(self.save_chains().map_err(|e| {
+            format!("can't save rebuilt chains, thus corpus wasn't changed: {}", e)
+        }));
+        try!(self.corpus.rewrite(&kept, self.config.backups));
+
+        let mut chains = vec![self.config.markov_file.clone()];
+        if self.config.group_chains {
+            chains.extend(self.group_chains.files());
+        }
+        for file in &chains {
+            if let Err(e) = for_files::remove_backups(file, self.config.backups) {
+                warn!("Failed to remove backups of `{}`: {}", file, e);
             }
         }
-        self.hashes.push(hashed);
-        Some(message)
+        Ok((removed, Some(rebuilt)))
+    }
+
+    /*
+        Explain why chain can't be rebuilt, and what to do about it.
+    */
+    fn not_rebuildable(&self) -> String {
+        format!("my chain has messages learned before I started keeping \
+                 the corpus, and rebuilding it would drop them. If that's \
+                 fine, use `{}rebuild force` first.", self.commands.prefix)
     }
 
     /**
-        Add string to markov chain if wasn't already added
+        Rebuild the global chain from `markov.txt` and the corpus, and
+        chains of groupchats from the corpus, if they're enabled. Chains
+        are rebuilt with order from config.
+
+        Unless `force` is given, it's refused if the chain isn't
+        rebuildable.
+
+        Returns description of what was rebuilt, with sizes of the global
+        chain before and after, so that it would be known what was dropped.
+        Size before is the one from the last time chain was loaded or saved,
+        since measuring it means encoding the whole chain.
+    */
+    fn rebuild(&mut self, force: bool) -> Result<String, String> {
+        if !force && !self.rebuildable {
+            return Err(self.not_rebuildable());
+        }
+
+        let entries = try!(self.load_corpus());
+        Ok(self.rebuild_from(&entries))
+    }
+
+    /*
+        Save the global chain along with hashes, and chains of groupchats if
+        they're enabled. Failures of all of them are returned together.
+    */
+    fn save_chains(&mut self) -> Result<(), String> {
+        let mut errors = vec![];
+        match for_files::save_chain(&self.markov, &self.config.markov_file,
+                                    self.config.backups, self.rebuildable) {
+            Ok(size) => {
+                self.stats.chain_size = size;
+                if let Err(e) = for_files::write_save(&self.config.hashes_file,
+                                                      self.hashes.to_bytes(),
+                                                      self.config.backups) {
+                    errors.push(format!("`{}`: {}", self.config.hashes_file, e));
+                }
+            },
+            Err(e) => errors.push(format!("`{}`: {}", self.config.markov_file, e)),
+        }
+
+        if self.config.group_chains {
+            if let Err(e) = self.group_chains.save() {
+                errors.push(format!("chains of groupchats: {}", e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /*
+        Load the corpus, or nothing if there's none yet.
+    */
+    fn load_corpus(&self) -> Result<Vec<corpus::Entry>, String> {
+        match corpus::load(&self.config.corpus_file, self.config.backups) {
+            Ok(e) => Ok(e),
+            Err(ref e) if fs::metadata(&self.config.corpus_file).is_err() => {
+                warn!("No corpus `{}`: {}", self.config.corpus_file, e);
+                Ok(vec![])
+            },
+            Err(e) => Err(format!("can't load corpus `{}`: {}",
+                                  self.config.corpus_file, e)),
+        }
+    }
+
+    /*
+        Rebuild chains from `markov.txt` and given entries of the corpus,
+        as described in `rebuild()`.
     */
-    fn add_to_markov(&mut self, message: &str) {
-        if let Some(msg) = self.check_hash(message.to_string()) {
-            self.markov.feed_str(&msg);
+    fn rebuild_from(&mut self, entries: &[corpus::Entry]) -> String {
+        let before = self.stats.chain_size;
+        self.markov = for_files::rebuild_chain(&self.config.markov_txt,
+                                               entries,
+                                               self.config.chain_order);
+        self.rebuildable = true;
+        if self.config.group_chains {
+            self.group_chains.rebuild(entries, self.config.chain_order);
+        }
+        self.stats.chain_size = stats::measure(&self.markov);
+        self.persist.force(Data::Chain, UTC::now().timestamp());
+
+        let size = |s: Option<(usize, usize)>| match s {
+            Some((states, tokens)) => format!("{} states and {} tokens",
+                                              states, tokens),
+            None => "unknown size".to_string(),
+        };
+        format!("Rebuilt chains from {} messages in the corpus and `{}`. \
+                 Chain had {}, now has {}.", entries.len(),
+                self.config.markov_txt, size(before),
+                size(self.stats.chain_size))
+    }
+
+
+    /**
+        Check whether peer `pnum` in groupchat `gnum` uses name that looks
+        like Lee's own, and if it does, warn about it – unless it was done
+        recently.
+
+        Peers with Lee's own public key, i.e. Lee itself, aren't impostors.
+    */
+    fn check_impostor(&mut self, gnum: i32, pnum: i32) {
+        let (name, pk) = match (self.tox.group_peername(gnum, pnum),
+                                self.tox.group_peer_pubkey(gnum, pnum)) {
+            (Some(name), Some(pk)) => (name, pk),
+            _ => return,
+        };
+        if pk == self.tox.public_key() || !self.impostors.is_impostor(&self.name, &name) {
+            return;
+        }
+
+        let key = format!("{}", pk).to_uppercase();
+        match self.impostors.warning(gnum, &key, &name, UTC::now().timestamp()) {
+            Some(warning) => {
+                info!("Impostor {:?} ({}) in groupchat {}.", name, key, gnum);
+                drop(self.tox.send_group_message(gnum, &warning));
+            },
+            None => debug!("Impostor {:?} ({}) in groupchat {}, already \
+                            warned.", name, key, gnum),
+        }
+    }
+
+    /**
+        Check whether peer with public key `pk` has exceeded limit of
+        `action` in `chat`, and if it hasn't, count the action.
+    */
+    fn rate_limited(&mut self, action: Action, pk: &PublicKey, chat: Chat)
+        -> bool
+    {
+        let group = match chat {
+            Chat::Group(gnum) => Some(gnum),
+            Chat::Friend(_) => None,
+        };
+        let key = format!("{}", pk).to_uppercase();
+        match self.limits.check(action, &key, group, UTC::now().timestamp()) {
+            Verdict::Allowed => false,
+            Verdict::Limited => {
+                debug!("{:?} limit of {:?} exceeded.", action, chat);
+                true
+            },
+            Verdict::CooldownStarted => {
+                info!("{} exceeded {:?} limit, ignoring it for {}s.",
+                      pk, action, self.limits.config.cooldown);
+                true
+            },
+            Verdict::CoolingDown => true,
+        }
+    }
+
+    /**
+        Check whether peer is ignored for exceeding rate limits.
+    */
+    fn cooling_down(&mut self, pk: &PublicKey) -> bool {
+        let key = format!("{}", pk).to_uppercase();
+        self.limits.cooling_down(&key, UTC::now().timestamp())
+    }
+
+
+    /**
+        Check whether peer with public key `pk` is allowed to use `command`.
+
+        If it isn't, send refusal to chat from which command came.
+    */
+    fn permitted(&mut self, pk: &PublicKey, command: &str, chat: Chat) -> bool {
+        match self.config.permissions.check(pk, command) {
+            Ok(()) => true,
+            Err(refusal) => {
+                drop(self.tox.send_message(chat, &refusal));
+                info!("Refused `{}` to {}.", command, pk);
+                false
+            },
+        }
+    }
+
+    /**
+        Report statistics, as separate lines.
+    */
+    fn stats_report(&self) -> Vec<String> {
+        let counts = stats::Counts {
+            hashes: self.hashes.len(),
+            friends: self.tox.friend_count(),
+            groups: self.tox.group_list().len(),
+        };
+        stats::report(&self.stats, &counts, UTC::now().timestamp())
+    }
+
+    /**
+        Apply config that was reloaded while Lee was running.
+
+        Only some of values can be changed that way, changing the rest of
+        them requires restart.
+    */
+    fn reload_config(&mut self, config: Config) {
+        if config.friend_requests != self.config.friend_requests {
+            info!("Friend request policy changed to: {:?}",
+                  config.friend_requests);
+            self.config.friend_requests = config.friend_requests;
+        }
+
+        if config.permissions != self.config.permissions {
+            info!("Roles and permissions changed.");
+            self.config.permissions = config.permissions;
+        }
+
+        if config.rate_limits != self.limits.config {
+            info!("Rate limits changed.");
+            self.limits.config = config.rate_limits.clone();
+            self.config.rate_limits = config.rate_limits;
+        }
+
+        if config.impostors != self.impostors.config {
+            info!("Impostor detection changed.");
+            self.impostors.config = config.impostors.clone();
+            self.config.impostors = config.impostors;
+        }
+
+        if config.triggers != self.triggers.config {
+            info!("Triggers changed.");
+            self.triggers = Triggers::new(&self.name, config.triggers.clone())
+                .unwrap();
+            self.config.triggers = config.triggers;
+        }
+
+        if config.learn_filter != self.config.learn_filter
+            || config.speak_filter != self.config.speak_filter {
+            // already validated when config was loaded
+            self.learn_filter = Filter::new(&config.learn_filter).unwrap();
+            self.speak_filter = Filter::new(&config.speak_filter).unwrap();
+            self.config.learn_filter = config.learn_filter;
+            self.config.speak_filter = config.speak_filter;
+            info!("Filters changed.");
         }
     }
 
@@ -206,78 +702,781 @@ impl Bot {
         Control status message.
 
         Takes an `Option<String>` as an argument, in a case where it's
-        `None`, default status message is being used, otherwise status message
-        is being changed to the new one, supplied `String`.
+        `None`, default status message from config is being used, otherwise
+        status message is being changed to the new one, supplied `String`.
     */
     fn status_message(&mut self, message: Option<String>) {
         match message {
             Some(m) => {
                 drop(self.tox.set_status_message(&m));
-                println!("{}: Status message set to: \"{}\"",
-                         UTC::now(), m);
+                info!("Status message set to: \"{}\"", m);
             },
             None => {
-                drop(self.tox.set_status_message("Send me a message 'invite' to get into the groupchat"));
-                println!("{}: Status message set to default one",
-                         UTC::now());
+                drop(self.tox.set_status_message(&self.config.status_message));
+                info!("Status message set to default one");
             },
         }
+        self.persist.mark(Data::Tox, UTC::now().timestamp());
+    }
+}
+
+
+impl Bot<Tox> {
+    /**
+        Save data that has changed, if it's time to do so.
+
+        Data that failed to be saved is marked as changed again, so that
+        saving it would be retried.
+    */
+    fn save_if_due(&mut self) {
+        let cur_time = UTC::now().timestamp();
+        let dirty = match self.persist.take_due(cur_time) {
+            Some(d) => d,
+            None => return,
+        };
+
+        if dirty.tox {
+            match for_files::write_save(&self.config.tox_save, self.tox.save(),
+                                        self.config.backups) {
+                Ok(_) => {
+                    info!("Saved `{}`", self.config.tox_save);
+                    self.stats.last_save = Some(cur_time);
+                },
+                Err(e) => {
+                    error!("Failed to save `{}`: {}", self.config.tox_save, e);
+                    self.persist.mark(Data::Tox, cur_time);
+                },
+            }
+        }
+
+        if dirty.chain {
+            match self.save_chains() {
+                Ok(_) => {
+                    info!("Saved `{}` and `{}`", self.config.markov_file,
+                          self.config.hashes_file);
+                    self.stats.last_save = Some(cur_time);
+                },
+                Err(e) => {
+                    error!("Failed to save {}", e);
+                    self.persist.mark(Data::Chain, cur_time);
+                },
+            }
+        }
     }
 }
 
 
 
 /*
-    Defend honour of a bot.
-    As extended measure, compares public key of peer.
+    Load hashes of messages that were already fed to markov chain.
+
+    In case where there are no saved hashes, start with none.
+*/
+fn load_hashes(config: &Config) -> Hashes {
+    let loaded = for_files::load_with_backups(&config.hashes_file,
+                                              config.backups,
+                                              |f| Hashes::load(f, config.max_hashes));
+    match loaded {
+        Ok(h) => {
+            info!("Loaded {} hashes from `{}`.", h.len(), config.hashes_file);
+            h
+        },
+        Err(e) => {
+            warn!("Error loading `{}`: {}", config.hashes_file, e);
+            Hashes::new(config.max_hashes)
+        },
+    }
+}
+
+
+/*
+    How many times Lee tries to generate message that passes speak filter,
+    before giving up.
 */
-const FAKE_NAMES: &'static [&'static str] = &["Lee", "Lee\0"];
+const GENERATE_TRIES: usize = 5;
 
 
 
 /*
     Function to deal with incoming friend requests
 
-    Currently accepts all by default
+    Accepts or rejects them according to policy from config, by default
+    accepts all.
 */
-// TODO: make it configurable to accept all / only selected FRs
-fn on_friend_request(tox: &mut Tox, fpk: PublicKey, msg: String) {
-    drop(tox.add_friend_norequest(&fpk));
-    println!("{}: Friend {} with friend message {:?} was added.",
-            UTC::now(), fpk, msg);
+fn on_friend_request<T: Transport>(bot: &mut Bot<T>, fpk: PublicKey,
+                                   msg: String) {
+    match bot.config.friend_requests.check(&fpk, &msg) {
+        Ok(()) => {
+            match bot.tox.add_friend(&fpk) {
+                Ok(_) => {
+                    info!("Friend {} with friend message {:?} was added.",
+                          fpk, msg);
+                    // don't risk losing new friend
+                    bot.persist.force(Data::Tox, UTC::now().timestamp());
+                },
+                Err(e) => warn!("Failed to add friend {}: {}", fpk, e),
+            }
+        },
+        Err(reason) => {
+            info!("Rejected friend request from {} with friend message {:?}: {}",
+                  fpk, msg, reason);
+        },
+    }
+}
+
+
+/*
+    Make registry with all commands that Lee understands.
+*/
+fn make_commands<T: Transport>(prefix: &str) -> Registry<Bot<T>> {
+    let mut commands = Registry::new(prefix);
+
+    commands.register(Command {
+        name: "about",
+        aliases: &[],
+        args: &[],
+        scope: Scope::Both,
+        description: "tell what Lee is",
+        examples: &["about"],
+        handler: cmd_about,
+    });
+
+    commands.register(Command {
+        name: "help",
+        aliases: &["commands"],
+        args: &[Arg::OptWord("command")],
+        scope: Scope::Both,
+        description: "list commands, or show how to use one of them",
+        examples: &["help", "help talk"],
+        handler: cmd_help,
+    });
+
+    commands.register(Command {
+        name: "id",
+        aliases: &[],
+        args: &[],
+        scope: Scope::Both,
+        description: "say Lee's Tox ID",
+        examples: &["id"],
+        handler: cmd_id,
+    });
+
+    commands.register(Command {
+        name: "groups",
+        aliases: &["groupchats"],
+        args: &[],
+        scope: Scope::Both,
+        description: "list groupchats that Lee is in",
+        examples: &["groups"],
+        handler: cmd_groups,
+    });
+
+    commands.register(Command {
+        name: "talk",
+        aliases: &[],
+        args: &[Arg::OptText("group")],
+        scope: Scope::Both,
+        description: "let Lee talk on its own in this or given groupchat",
+        examples: &["talk", "talk 0", "talk Tox Public Chat"],
+        handler: cmd_talk,
+    });
+
+    commands.register(Command {
+        name: "stahp",
+        aliases: &["stop"],
+        args: &[Arg::OptText("group")],
+        scope: Scope::Both,
+        description: "stop Lee from talking on its own in this or given \
+                      groupchat",
+        examples: &["stahp", "stahp 0", "stahp Tox Public Chat"],
+        handler: cmd_stahp,
+    });
+
+    commands.register(Command {
+        name: "say",
+        aliases: &[],
+        args: &[Arg::Word("word")],
+        scope: Scope::Both,
+        description: "say something, starting with given word",
+        examples: &["say Tox"],
+        handler: cmd_say,
+    });
+
+    commands.register(Command {
+        name: "stats",
+        aliases: &[],
+        args: &[],
+        scope: Scope::Both,
+        description: "tell how much Lee has learned, and how it's doing",
+        examples: &["stats"],
+        handler: cmd_stats,
+    });
+
+    commands.register(Command {
+        name: "forget",
+        aliases: &[],
+        args: &[Arg::Text("phrase|/regex/")],
+        scope: Scope::Both,
+        description: "forget learned messages containing phrase, or \
+                      matching regex",
+        examples: &["forget some phrase", "forget /^https?:/"],
+        handler: cmd_forget,
+    });
+
+    commands.register(Command {
+        name: "rebuild",
+        aliases: &[],
+        args: &[Arg::OptWord("force")],
+        scope: Scope::Both,
+        description: "rebuild chains from the corpus; `force` is needed if \
+                      chain has messages that aren't in it",
+        examples: &["rebuild", "rebuild force"],
+        handler: cmd_rebuild,
+    });
+
+    commands.register(Command {
+        name: "ignore",
+        aliases: &[],
+        args: &[Arg::OptText("public key|name")],
+        scope: Scope::Both,
+        description: "stop learning from and reacting to a peer, given by \
+                      public key or by name in this groupchat, or list \
+                      ignored peers",
+        examples: &["ignore", "ignore SomeBot",
+                    "ignore 04119E835DF3E78BACF0F84235B300546AF8B936F035185E2A8E9E0A67C8924F"],
+        handler: cmd_ignore,
+    });
+
+    commands.register(Command {
+        name: "unignore",
+        aliases: &[],
+        args: &[Arg::Text("public key|name")],
+        scope: Scope::Both,
+        description: "stop ignoring a peer",
+        examples: &["unignore SomeBot"],
+        handler: cmd_unignore,
+    });
+
+    commands.register(Command {
+        name: "invite",
+        aliases: &[],
+        args: &[Arg::OptText("group")],
+        scope: Scope::Friend,
+        description: "get invited to given groupchat, or to the last active \
+                      one",
+        examples: &["invite", "invite 0", "invite Tox Public Chat"],
+        handler: cmd_invite,
+    });
+
+    commands
+}
+
+
+/*
+    Handle command in a message, if there is one.
+
+    Returns `true` if message was a command, in which case it shouldn't be
+    treated as a normal message.
+
+    Messages from Lee itself are never treated as commands.
+*/
+fn handle_command<T: Transport>(bot: &mut Bot<T>, msg: &str, chat: Chat,
+                                pk: &PublicKey) -> bool {
+    if *pk == bot.tox.public_key() {
+        return false;
+    }
+
+    let parsed = bot.commands.parse(msg, chat, pk);
+    if let Parsed::NotCommand = parsed {
+        return false;
+    }
+    if bot.rate_limited(Action::Command, pk, chat) {
+        return true;
+    }
+
+    match parsed {
+        Parsed::NotCommand => false,
+        Parsed::Invalid(name, reply) => {
+            debug!("Invalid use of `{}` by {}: {}", name, pk, reply);
+            drop(bot.tox.send_message(chat, &reply));
+            true
+        },
+        Parsed::Command(handler, call) => {
+            if bot.permitted(pk, call.name, chat) {
+                debug!("Command `{}` {:?} used by {}", call.name, call.args, pk);
+                handler(bot, &call);
+            }
+            true
+        },
+    }
 }
 
 
+/*
+    Send "about" message
+*/
+fn cmd_about<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = match call.chat {
+        Chat::Friend(_) => format!(
+"Lee is libre software, licensed under GPLv3+.
+
+Uses Supreme Tox technology.
+
+Made by Zetok\0.
+Many thanks to all the people who helped in making it.
+
+For more info, visit: https://gitlab.com/zetok/Lee"),
+        Chat::Group(_) => format!(
+"Lee is libre software, licensed under GPLv3+.
+
+Made by Zetok\0.
+Many thanks to all the people who helped in making it.
+
+For more info, visit: https://github.com/zetok/Lee"),
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    List commands that caller can use in chat from which `.help` came,
+    along with caller's role.
+
+    With a command name as an argument, show usage, aliases, required role
+    and examples of that command instead.
+*/
+fn cmd_help<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let role = bot.config.permissions.role(&call.pk);
+
+    let message = match call.args.first() {
+        None => {
+            let mut message = format!("Your role: {}. Commands that you can \
+                                       use here:", role);
+            for command in bot.commands.commands() {
+                if command.scope.allows(call.chat)
+                    && bot.config.permissions.required(command.name) <= role {
+                    message.push_str(&format!("\n{} – {}",
+                                              bot.commands.usage(command),
+                                              command.description));
+                }
+            }
+            message.push_str(&format!("\nUse `{}help <command>` for details.",
+                                      bot.commands.prefix));
+            message
+        },
+        Some(name) => match bot.commands.find(name) {
+            None => format!("There's no `{}` command. Use `{}help` to list \
+                             commands.", name, bot.commands.prefix),
+            Some(command) => {
+                let prefix = &bot.commands.prefix;
+                let mut message = format!("Usage: {}\n{}",
+                                          bot.commands.usage(command),
+                                          command.description);
+                if !command.aliases.is_empty() {
+                    let aliases: Vec<String> = command.aliases.iter()
+                        .map(|a| format!("{}{}", prefix, a)).collect();
+                    message.push_str(&format!("\nAliases: {}",
+                                              aliases.join(", ")));
+                }
+                let place = match command.scope {
+                    Scope::Group => "groupchats",
+                    Scope::Friend => "friend chats",
+                    Scope::Both => "groupchats and friend chats",
+                };
+                message.push_str(&format!("\nCan be used in {} by {}s and up; \
+                                           you're a {}.", place,
+                                          bot.config.permissions.required(command.name),
+                                          role));
+                for example in command.examples {
+                    message.push_str(&format!("\nExample: {}{}", prefix, example));
+                }
+                message
+            },
+        },
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Allow anyone with required role to get Lee's ID
+*/
+fn cmd_id<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = format!("My ID: {}", bot.tox.address());
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Generate sentence starting with the word given as an argument.
+*/
+fn cmd_say<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let word = &call.args[0];
+    let seeds = [word.clone()];
+    let local = bot.local_chain(call.chat)
+        .and_then(|k| bot.group_chains.get(&k))
+        .and_then(|group| reply::generate_from(&group.chain, &seeds));
+    let message = match local.or_else(|| reply::generate_from(&bot.markov, &seeds)) {
+        Some(m) => match bot.speak_filter.apply(&m) {
+            Ok(m) => m,
+            Err(_) => "I'd rather not say that.".to_string(),
+        },
+        None => format!("I don't know anything about {:?}.", word),
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Send statistics.
+*/
+fn cmd_stats<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = bot.stats_report().join("\n");
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Forget learned messages matching phrase or regex.
+*/
+fn cmd_forget<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = match corpus::Pattern::parse(&call.args[0]) {
+        Err(e) => format!("Invalid regex: {}", e),
+        Ok(pattern) => match bot.forget(&pattern) {
+            Err(e) => {
+                error!("Failed to forget {:?}: {}", call.args[0], e);
+                format!("Sorry, I couldn't forget that: {}", e)
+            },
+            Ok((removed, rebuilt)) => {
+                info!("Forgot {} messages matching {:?}.", removed, call.args[0]);
+                let in_txt = for_files::count_txt_matches(&bot.config.markov_txt,
+                                                          &pattern);
+                let mut message = format!("Forgot {} messages.", removed);
+                if let Some(rebuilt) = rebuilt {
+                    message.push_str(&format!(" {} Backups of the corpus and \
+                                               chains that had them are \
+                                               removed.", rebuilt));
+                }
+                if in_txt > 0 {
+                    message.push_str(&format!(" {} more are in `{}`, which \
+                                               has to be edited by hand.",
+                                              in_txt, bot.config.markov_txt));
+                }
+                message
+            },
+        },
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Rebuild chains from the corpus. If chain has messages that aren't in
+    the corpus, `force` is needed.
+*/
+fn cmd_rebuild<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let force = match call.args.first() {
+        None => false,
+        Some(arg) if arg == "force" => true,
+        Some(arg) => {
+            let message = format!("Did you mean `{}rebuild force`, rather \
+                                   than {:?}?", bot.commands.prefix, arg);
+            drop(bot.tox.send_message(call.chat, &message));
+            return;
+        },
+    };
+
+    let message = match bot.rebuild(force) {
+        Ok(rebuilt) => {
+            info!("{}", rebuilt);
+            rebuilt
+        },
+        Err(e) => {
+            error!("Failed to rebuild chains: {}", e);
+            format!("Sorry, I couldn't rebuild chains: {}", e)
+        },
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Find public key of a peer given as command argument – either as
+    a public key or Tox ID, or, in groupchat, as a name of peer in it.
+*/
+fn find_peer<T: Transport>(bot: &Bot<T>, call: &Call, peer: &str)
+    -> Result<String, String>
+{
+    if let Some(key) = friend_requests::normalize_key(peer) {
+        return Ok(key);
+    }
+
+    if let Chat::Group(gnum) = call.chat {
+        let peers = bot.tox.group_number_peers(gnum).unwrap_or(0);
+        let wanted = peer.to_lowercase();
+        let matching: Vec<PublicKey> = (0..peers).filter(|&pnum| {
+            bot.tox.group_peername(gnum, pnum)
+                .map(|name| name.to_lowercase() == wanted).unwrap_or(false)
+        }).filter_map(|pnum| bot.tox.group_peer_pubkey(gnum, pnum)).collect();
+
+        match matching.len() {
+            0 => {},
+            1 => return Ok(format!("{}", matching[0]).to_uppercase()),
+            _ => return Err(format!("There's more than one {:?} here, use \
+                                     public key instead.", peer)),
+        }
+    }
+
+    Err(format!("{:?} isn't a public key, or a name of peer in this \
+                 groupchat.", peer))
+}
+
+
+/*
+    Ignore a peer, or list ignored peers.
+
+    Peers with the same or higher role than caller's can't be ignored.
+
+    List is split into as many messages as needed to fit in Tox's limit of
+    length of a message.
+*/
+fn cmd_ignore<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = match call.args.first() {
+        None => {
+            let mut lines = vec!["Ignored peers:".to_string()];
+            lines.extend(bot.ignored.keys());
+            if lines.len() == 1 {
+                "Nobody is ignored.".to_string()
+            } else {
+                for message in transport::join_lines(&lines) {
+                    if let Err(e) = bot.tox.send_message(call.chat, &message) {
+                        warn!("Failed to send list of ignored peers: {}", e);
+                        return;
+                    }
+                }
+                return;
+            }
+        },
+        Some(peer) => match find_peer(bot, call, peer) {
+            Err(e) => e,
+            Ok(key) => {
+                let role = bot.config.permissions.role(&call.pk);
+                if bot.config.permissions.role_of_key(&key) >= role
+                    || key == format!("{}", bot.tox.public_key()).to_uppercase() {
+                    format!("Sorry, I can't ignore {:?}.", peer)
+                } else {
+                    match bot.ignored.add(&key) {
+                        Ok(true) => {
+                            info!("{} ignored by {}.", key, call.pk);
+                            format!("Ignoring {}.", key)
+                        },
+                        Ok(false) => format!("{} is already ignored.", key),
+                        Err(e) => {
+                            error!("Failed to save ignore list: {}", e);
+                            format!("Ignoring {}, but I couldn't save that: {}",
+                                    key, e)
+                        },
+                    }
+                }
+            },
+        },
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Stop ignoring a peer.
+*/
+fn cmd_unignore<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let message = match find_peer(bot, call, &call.args[0]) {
+        Err(e) => e,
+        Ok(key) => match bot.ignored.remove(&key) {
+            Ok(true) => {
+                info!("{} unignored by {}.", key, call.pk);
+                format!("Not ignoring {} anymore.", key)
+            },
+            Ok(false) => format!("{} isn't ignored.", key),
+            Err(e) => {
+                error!("Failed to save ignore list: {}", e);
+                format!("Not ignoring {} anymore, but I couldn't save that: {}",
+                        key, e)
+            },
+        },
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Find groupchat given as command argument, either by its number, or by
+    its title. Title doesn't have to be complete, as long as only one
+    groupchat matches it.
+
+    Without an argument, groupchat from which command came is used, or, if
+    it came from a friend, `default`.
+*/
+fn find_group<T: Transport>(bot: &Bot<T>, call: &Call, default: Option<i32>)
+    -> Result<i32, String>
+{
+    let groups = bot.tox.group_list();
+    let arg = match call.args.first() {
+        Some(arg) => arg,
+        None => return match (call.chat, default) {
+            (Chat::Group(gnum), _) => Ok(gnum),
+            (Chat::Friend(_), Some(gnum)) if groups.contains(&gnum) => Ok(gnum),
+            (Chat::Friend(_), _) => Err(format!(
+                "Which groupchat? Use `{}groups` to list them.",
+                bot.commands.prefix)),
+        },
+    };
+
+    if let Ok(gnum) = arg.parse::<i32>() {
+        if groups.contains(&gnum) {
+            return Ok(gnum);
+        }
+    }
+
+    let wanted = arg.to_lowercase();
+    let titled: Vec<(i32, String)> = groups.iter().map(|&g| {
+        (g, bot.tox.group_title(g).unwrap_or(String::new()).to_lowercase())
+    }).collect();
+
+    if let Some(&(gnum, _)) = titled.iter().find(|&&(_, ref t)| *t == wanted) {
+        return Ok(gnum);
+    }
+
+    let matching: Vec<i32> = titled.iter()
+        .filter(|&&(_, ref t)| t.contains(&wanted))
+        .map(|&(g, _)| g).collect();
+    match matching.len() {
+        1 => Ok(matching[0]),
+        0 => Err(format!("I'm not in groupchat {:?}. Use `{}groups` to list \
+                          groupchats.", arg, bot.commands.prefix)),
+        _ => Err(format!("{:?} matches more than one groupchat. Use its \
+                          number instead, from `{}groups`.",
+                         arg, bot.commands.prefix)),
+    }
+}
+
+
+/*
+    List groupchats that Lee is in, with their titles and number of peers.
+*/
+fn cmd_groups<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    let groups = bot.tox.group_list();
+    let message = if groups.is_empty() {
+        "I'm not in any groupchat.".to_string()
+    } else {
+        let mut message = "Groupchats:".to_string();
+        for gnum in groups {
+            let title = bot.tox.group_title(gnum)
+                .unwrap_or_else(|| "•no title•".to_string());
+            let peers = bot.tox.group_number_peers(gnum).unwrap_or(0);
+            message.push_str(&format!("\n{}: {} ({} peers)", gnum, title, peers));
+        }
+        message
+    };
+    drop(bot.tox.send_message(call.chat, &message));
+}
+
+
+/*
+    Allow those with required role to turn speaking `on / off` in
+    groupchat, and if switch is changed, tell about it in that groupchat.
+
+    Switch affects only one groupchat – the one in which command was sent,
+    or the one given as an argument.
+*/
+fn set_speak<T: Transport>(bot: &mut Bot<T>, call: &Call, speak: bool) {
+    let gnum = match find_group(bot, call, None) {
+        Ok(g) => g,
+        Err(e) => {
+            drop(bot.tox.send_message(call.chat, &e));
+            return;
+        },
+    };
+
+    let state = if speak { "on" } else { "off" };
+    if bot.group(gnum).speak != speak {
+        bot.group(gnum).speak = speak;
+        drop(bot.tox.send_group_message(gnum, &format!(
+                    "Talking on my own in this groupchat: {}", state)));
+        info!("Talking in group {} turned {}.", gnum, state);
+    }
+
+    if call.chat != Chat::Group(gnum) {
+        drop(bot.tox.send_message(call.chat, &format!(
+                    "Talking on my own in groupchat {}: {}", gnum, state)));
+    }
+}
+
+fn cmd_talk<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    set_speak(bot, call, true);
+}
+
+fn cmd_stahp<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    set_speak(bot, call, false);
+}
+
+
+/*
+    Invite friend to given groupchat, or to the last one in which someone
+    spoke.
+
+    TODO: make it possible to print to stdout friend's name when inviting
+*/
+fn cmd_invite<T: Transport>(bot: &mut Bot<T>, call: &Call) {
+    if let Chat::Friend(fnum) = call.chat {
+        let last_group = bot.last_group;
+        let reply = match find_group(bot, call, Some(last_group)) {
+            Err(e) => e,
+            Ok(gnum) => match bot.tox.invite_friend(fnum, gnum) {
+                Ok(()) => {
+                    info!("Sent invitation to friend {} to groupchat {}",
+                          fnum, gnum);
+                    return;
+                },
+                Err(e) => {
+                    warn!("Failed to invite friend {} to groupchat {}: {}",
+                          fnum, gnum, e);
+                    format!("Sorry, I couldn't invite you to groupchat {}: {}",
+                            gnum, e)
+                },
+            },
+        };
+        drop(bot.tox.send_friend_message(fnum, &reply));
+    }
+}
+
+
+
 /*
     Function to deal with friend messages.
 
     Lee is supposed to answer all friend messages, in ~similar way to
     how it's done in groupchats.
 
-    The only **exception** is inviting friends to last groupchat in which
-    someone spoke in - in this case Lee should return early.
+    The only **exception** are commands, e.g. inviting friends to last
+    groupchat in which someone spoke in - in this case Lee should return
+    early, to not feed markov with command.
 */
-fn on_friend_message(bot: &mut Bot, fnum: u32, msg: String) {
-    let pubkey = match bot.tox.get_friend_public_key(fnum) {
+fn on_friend_message<T: Transport>(bot: &mut Bot<T>, fnum: u32, msg: String) {
+    let pubkey = match bot.tox.friend_public_key(fnum) {
         Some(pkey) => pkey,
-        None       => bot.tox.get_public_key(),
+        None       => bot.tox.public_key(),
     };
 
-    /*
-        Invite friend and return early, to not feed markov with invite
-        command.
+    debug!(target: logging::MESSAGES, "Friend {} sent message: {}",
+           pubkey, &msg);
 
-        TODO: make it possible to print to stdout friend's name when inviting
-    */
-    if &msg == "invite" {
-        drop(bot.tox.invite_friend(fnum as i32, bot.last_group));
-        println!("{}: Sent invitation to friend {} to groupchat {}",
-            UTC::now(), fnum, bot.last_group);
+    if bot.ignored.is_ignored(&pubkey) || bot.cooling_down(&pubkey) {
         return;
     }
 
-    println!("{}: Event: FriendMessage:\nFriend {} sent message: {}",
-            UTC::now(), pubkey, &msg);
+    if handle_command(bot, &msg, Chat::Friend(fnum), &pubkey) {
+        return;
+    }
 
     /*
         feed Lee with message content, but only if peer PK doesn't match
@@ -285,31 +1484,18 @@ fn on_friend_message(bot: &mut Bot, fnum: u32, msg: String) {
 
         Feeding Lee with what it threw up may not be a good idea after all..
     */
-    if pubkey != bot.tox.get_public_key() {
-        bot.add_to_markov(&msg);
+    if pubkey != bot.tox.public_key() {
+        bot.add_to_markov(&msg, Chat::Friend(fnum), &pubkey);
     }
 
+    if bot.rate_limited(Action::FriendReply, &pubkey, Chat::Friend(fnum)) {
+        return;
+    }
 
-    /*
-        Send "about" message
-    */
-    if msg == ".about" || msg == ".help" {
-        let message = format!(
-"Lee is libre software, licensed under GPLv3+.
-
-Uses Supreme Tox technology.
-
-Made by Zetok\0.
-Many thanks to all the people who helped in making it.
-
-For more info, visit: https://gitlab.com/zetok/Lee");
-        drop(bot.tox.send_friend_message(fnum, MessageType::Normal, &message));
-        println!("{}: Sent \"About\" message to friend {}", UTC::now(), fnum);
-    } else {
-        let message = bot.markov.generate_str();
-        println!("Answer: {}", &message);
-        drop(bot.tox.send_friend_message(fnum, MessageType::Normal, &message));
-        println!("{}: Sent random message to friend {}", UTC::now(), fnum);
+    if let Some(message) = bot.generate(Chat::Friend(fnum), None) {
+        debug!(target: logging::MESSAGES, "Answer to friend {}: {}",
+               fnum, &message);
+        drop(bot.tox.send_friend_message(fnum, &message));
     }
 }
 
@@ -318,20 +1504,19 @@ For more info, visit: https://gitlab.com/zetok/Lee");
 /*
     Function to deal with incoming invites to groupchats
 */
-fn on_group_invite(tox: &mut Tox, fid: i32, kind: GroupchatType, data: Vec<u8>) {
+fn on_group_invite<T: Transport>(tox: &mut T, fid: i32, kind: GroupchatType,
+                                  data: Vec<u8>) {
     /*
         Since rstox currently supports only text groupchats, handle only them,
         and drop other invites.
     */
     match kind {
         GroupchatType::Text => {
-            drop(tox.join_groupchat(fid, &data));
-            println!("{}: Accepted invite to text groupchat by {}.",
-                    UTC::now(), fid);
+            drop(tox.join_group(fid, &data));
+            info!("Accepted invite to text groupchat by {}.", fid);
         },
         GroupchatType::Av => {
-            println!("{}: Declined invite to audio groupchat by {}.",
-                    UTC::now(), fid);
+            info!("Declined invite to audio groupchat by {}.", fid);
         },
     }
 }
@@ -340,7 +1525,8 @@ fn on_group_invite(tox: &mut Tox, fid: i32, kind: GroupchatType, data: Vec<u8>)
 /*
     Function to deal with group messages
 */
-fn on_group_message(bot: &mut Bot, gnum: i32, pnum: i32, msg: String) {
+fn on_group_message<T: Transport>(bot: &mut Bot<T>, gnum: i32, pnum: i32,
+                                   msg: String) {
     /*
         Get PK of the peer who sent message
 
@@ -349,22 +1535,33 @@ fn on_group_message(bot: &mut Bot, gnum: i32, pnum: i32, msg: String) {
     */
     let pubkey = match bot.tox.group_peer_pubkey(gnum, pnum) {
         Some(pkey) => pkey,
-        None       => bot.tox.get_public_key(),
+        None       => bot.tox.public_key(),
     };
 
 
     // mark this groupchat as last active one
     bot.last_group = gnum;
+    bot.group(gnum);
 
 
     /*
         Triggers Lee
     */
-    fn trigger_response(msg: &String, bot: &mut Bot) {
-        // check whether name is mentioned — convert message to lowercase and
-        // then look for lowercase name of bot in message
-        if msg.to_lowercase().contains(&bot.name.to_lowercase()) {
-            bot.trigger = true;
+    fn trigger_response<T: Transport>(msg: &String, bot: &mut Bot<T>,
+                                      gnum: i32, pubkey: &PublicKey) {
+        // check whether name or alias is mentioned as a whole word, or
+        // whether message is a question to everyone that Lee feels like
+        // answering – one that starts with name of a peer followed by a
+        // comma is asked to that peer
+        let count = bot.tox.group_number_peers(gnum).unwrap_or(0);
+        let peers: Vec<String> = (0..count)
+            .filter_map(|pnum| bot.tox.group_peername(gnum, pnum))
+            .collect();
+        let triggered = bot.triggers.is_mentioned(msg)
+            || (bot.triggers.is_room_question(msg, &peers)
+                && bot.triggers.config.question_chance > bot.random.gen::<f64>());
+        if triggered
+            && !bot.rate_limited(Action::Trigger, pubkey, Chat::Group(gnum)) {
             /*
                 ↓ waiting time for response should be random, for more
                 human-like feel, and should be at least 2s long – too
@@ -373,90 +1570,44 @@ fn on_group_message(bot: &mut Bot, gnum: i32, pnum: i32, msg: String) {
                 Currently waiting time should be between 1 and 5s.
             */
             let random_wait = 1.0 + 4.0 * bot.random.gen::<f64>();
-            bot.trigger_time = random_wait as i64 + UTC::now().timestamp();
+            bot.group(gnum).set_trigger(random_wait as i64, msg);
         }
     }
 
     match bot.tox.group_peername(gnum, pnum) {
         Some(pname) => {
-            /*
-                feed Lee with message content, but only if peer PK doesn't match
-                Lee's own PK
-
-                Feeding Lee with what it threw up may not be a good idea after
-                all..
-            */
-
-            if pubkey != bot.tox.get_public_key() {
-                bot.add_to_markov(&msg);
-            }
-
-            if FAKE_NAMES.contains(&&*pname) {
-                if pubkey != bot.tox.get_public_key() {
-                    drop(bot.tox.group_message_send(gnum, "↑ an impostor!"));
-                }
-            }
-
-
-            if pubkey != bot.tox.get_public_key() {
-                trigger_response(&msg, bot);
-            }
-
-            println!("{}: Event: GroupMessage({}, {}, {:?}), Name: {:?}, PK: {}",
-                    UTC::now(), gnum, pnum, msg, pname, pubkey);
+            debug!(target: logging::MESSAGES,
+                   "GroupMessage({}, {}, {:?}), Name: {:?}, PK: {}",
+                   gnum, pnum, msg, pname, pubkey);
         },
 
         None => {
-            if pubkey != bot.tox.get_public_key() {
-                trigger_response(&msg, bot);
-            }
-
-            println!("{}: Event: GroupMessage({}, {}, {:?}), Name: •not known•, PK: {}",
-                    UTC::now(), gnum, pnum, msg, pubkey);
+            debug!(target: logging::MESSAGES,
+                   "GroupMessage({}, {}, {:?}), Name: •not known•, PK: {}",
+                   gnum, pnum, msg, pubkey);
         },
     }
 
-    /*
-        Allow anyone to turn speaking `on / off`, and if switch is changed,
-        alter status message accordingly.
-    */
+    if bot.ignored.is_ignored(&pubkey) || bot.cooling_down(&pubkey) {
+        return;
+    }
 
-    let re = Regex::new(r"^(\[[a-zA-Z0-9\|_^`]*\])?\s?\.stahp").unwrap();
+    bot.check_impostor(gnum, pnum);
 
-    if re.is_match(&msg) {
-        if bot.speak == true {
-            bot.speak = false;
-            let new_status = format!("{} | groupchat talk: off",
-                                     bot.tox.get_status_message());
-            bot.status_message(Some(new_status));
-        }
-    } else if msg == ".talk" {
-        if bot.speak == false {
-            bot.speak = true;
-            bot.status_message(None);
-        }
+    if handle_command(bot, &msg, Chat::Group(gnum), &pubkey) {
+        return;
     }
 
     /*
-        Allow anyone to get Lee's ID
-    */
-    if msg == ".id" && pubkey != bot.tox.get_public_key() {
-        let message = format!("My ID: {}", bot.tox.get_address());
-        drop(bot.tox.group_message_send(gnum, &message));
-    }
+        feed Lee with message content, but only if peer PK doesn't match
+        Lee's own PK
 
-    /*
-        Send "about" message
+        Feeding Lee with what it threw up may not be a good idea after
+        all..
     */
-    if msg == ".about" || msg == ".help" {
-        let message = format!(
-"Lee is libre software, licensed under GPLv3+.
-
-Made by Zetok\0.
-Many thanks to all the people who helped in making it.
-
-For more info, visit: https://github.com/zetok/Lee");
-        drop(bot.tox.group_message_send(gnum, &message));
+    if pubkey != bot.tox.public_key() {
+        bot.add_to_markov(&msg, Chat::Group(gnum), &pubkey);
+        trigger_response(&msg, bot, gnum, &pubkey);
     }
 }
 
@@ -465,53 +1616,129 @@ For more info, visit: https://github.com/zetok/Lee");
     Function to deal with namechanges in groupchat
 
     Upon detecting that someone leaves, bot should check how many peers are
-    left, and if there is only 1 peer (bot), automatically leave groupchat.
+    left, and if there is only 1 peer (bot), automatically leave groupchat,
+    and forget its state, since group number can be reused for another
+    groupchat.
 
     After leaving groupchat, print info about it.
 
-    In case of other event, print info about it.
+    In case of other event, print info about it, and check whether peer
+    that joined or changed name isn't an impostor – unless peer is ignored,
+    in which case Lee doesn't react to it.
 */
-fn on_group_namelist_change(tox: &mut Tox, gnum: i32, pnum: i32,
-                            change: ChatChange) {
+fn on_group_namelist_change<T: Transport>(bot: &mut Bot<T>, gnum: i32,
+                                          pnum: i32, change: ChatChange) {
     if let ChatChange::PeerDel = change {
-        println!("{}: Event: Groupchat {}, Peer {} left.",
-                UTC::now(), gnum, pnum);
-        if let Some(peers) = tox.group_number_peers(gnum) {
+        debug!("Groupchat {}, Peer {} left.", gnum, pnum);
+        if let Some(peers) = bot.tox.group_number_peers(gnum) {
             if peers == 1 {
-                drop(tox.del_groupchat(gnum));
-                println!("{}: Left empty group {}.", UTC::now(), gnum);
+                drop(bot.tox.leave_group(gnum));
+                bot.groups.remove(&gnum);
+                info!("Left empty group {}.", gnum);
             }
         }
     } else {
-        println!("{}: Event: Groupchat {}, Peer {}: {:?}",
-                UTC::now(), gnum, pnum, change);
+        debug!("Groupchat {}, Peer {}: {:?}", gnum, pnum, change);
+        if let Some(pk) = bot.tox.group_peer_pubkey(gnum, pnum) {
+            if bot.ignored.is_ignored(&pk) || bot.cooling_down(&pk) {
+                return;
+            }
+        }
+        // peer that joined or changed name may be pretending to be Lee
+        bot.check_impostor(gnum, pnum);
     }
 }
 
 
 fn main() {
     /*
-        Try to load data file, if not possible, print an error and generate
-        new Tox instance.
+        Load config file – either one supplied as first argument, or
+        `lee.toml` from working directory. Only the latter may be missing,
+        in which case default config is used.
+
+        Arguments starting with `--` are options, e.g. `--rebuild-chain`.
+
+        In case where config is invalid, there's no point in running with
+        something else than what was asked for, thus exit.
+    */
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().skip(1)
+        .partition(|a| a.starts_with("--"));
+    let rebuild_chain = flags.iter().any(|f| f == "--rebuild-chain");
+    if let Some(f) = flags.iter().find(|f| *f != "--rebuild-chain") {
+        println!("{}: Unknown option `{}`", UTC::now(), f);
+        std::process::exit(1);
+    }
+
+    let config_arg = args.into_iter().next();
+    let config_required = config_arg.is_some();
+    let config_file = config_arg.unwrap_or(config::CONFIG_FILE.to_string());
+    let mut config_watcher = ConfigWatcher::new(&config_file);
+    let loaded = if config_required {
+        Config::load(&config_file)
+    } else {
+        Config::load_or_default(&config_file)
+    };
+    let config = match loaded {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}: {}", UTC::now(), e);
+            std::process::exit(1);
+        },
+    };
+
+    /*
+        Logging should be set up as early as possible, but it depends on
+        config – thus errors before that point can only be printed.
     */
-    let data = match for_files::load_save("lee.tox") {
-        Ok(d) => {
-            println!("{}: Savefile `lee.tox` loaded.", UTC::now());
-            Some(d)
+    if let Err(e) = logging::init(&config.logging) {
+        println!("{}: Failed to set up logging: {}", UTC::now(), e);
+        std::process::exit(1);
+    }
+
+    /*
+        Try to load data file, or the newest of its backups that toxcore
+        accepts. If not possible, print an error and generate new Tox
+        instance.
+    */
+    let loaded = for_files::load_with_backups(&config.tox_save, config.backups,
+        |f| for_files::load_save(f).and_then(|d| {
+            Tox::new(ToxOptions::new(), Some(&d[..]))
+                .map_err(|e| format!("invalid save: {:?}", e))
+        }));
+    let tox = match loaded {
+        Ok(t) => {
+            info!("Savefile `{}` loaded.", config.tox_save);
+            t
         },
         Err(e) => {
-            println!("{}: Error loading save: {}", UTC::now(), e);
-            None
+            warn!("Error loading save: {}", e);
+            Tox::new(ToxOptions::new(), None).unwrap()
         },
     };
 
     /*
         Bot stuff
     */
-    let mut bot = Bot::new(data);
+    let (markov, rebuildable) = if rebuild_chain {
+        (for_files::new_chain(config.chain_order), true)
+    } else {
+        for_files::make_chain(&config.markov_file, &config.markov_txt,
+                              config.chain_order, config.backups)
+    };
+    let mut bot = Bot::new(tox, config, markov, rebuildable);
+    if rebuild_chain {
+        match bot.rebuild(true) {
+            Ok(rebuilt) => info!("{}", rebuilt),
+            Err(e) => {
+                error!("Failed to rebuild chains: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
 
     drop(bot.tox.set_name(&bot.name));
     bot.status_message(None);
+    bot.persist.mark(Data::Tox, UTC::now().timestamp());
 
 
     /*
@@ -519,22 +1746,26 @@ fn main() {
         During bootstrapping one should query random bootstrap nodes from a
         supplied list; in case where there is no list, rely back on hardcoded
         bootstrap nodes.
-        // TODO: actually make it possible to use supplied list; location of a
-        //       list should be determined by value supplied in config file;
-        //       in case of absence of config file, working dir should be
-        //       tried for presence of file named `bootstrap.txt`, only if it
-        //       is missing fall back on hardcoded nodes
+
+        Location of a list is determined by value supplied in config file,
+        which defaults to `bootstrap.txt` in working dir.
     */
-    bootstrap::bootstrap_hardcoded(&mut bot.tox);
+    if !bootstrap::bootstrap_from_file(&mut bot.tox,
+                                       &bot.config.bootstrap_file,
+                                       bot.config.bootstrap_nodes) {
+        warn!("Falling back on hardcoded bootstrap nodes.");
+        bootstrap::bootstrap_hardcoded(&mut bot.tox);
+    }
 
-    println!("\nMy ID: {}", bot.tox.get_address());
-    println!("My name: {:?}", bot.tox.get_name());
+    info!("My ID: {}", bot.tox.address());
+    info!("My name: {:?}", bot.tox.get_name());
+    info!("Stats: {}", bot.stats_report().join(", "));
 
     loop {
         for ev in bot.tox.iter() {
             match ev {
                 FriendRequest(fpk, msg) => {
-                    on_friend_request(&mut bot.tox, fpk, msg);
+                    on_friend_request(&mut bot, fpk, msg);
                 },
 
                 FriendMessage(fnum, _, msg) => {
@@ -550,66 +1781,307 @@ fn main() {
                 },
 
                 GroupNamelistChange(gnum, pnum, change) => {
-                    on_group_namelist_change(&mut bot.tox, gnum, pnum, change);
+                    on_group_namelist_change(&mut bot, gnum, pnum, change);
                 },
 
-                ev => { println!("{}: Event: {:?}", UTC::now(), ev); },
+                ev => { trace!("Event: {:?}", ev); },
             }
         }
 
 
         /*
-            Let Lee speak when triggered, provided that it will wait required
-            amount of time.
+            Reload config if it was changed. In case where it became
+            invalid or was removed, keep using old one.
         */
-        if bot.trigger {
-            let cur_time = UTC::now().timestamp();
-            if cur_time >= bot.trigger_time {
-                let message = bot.markov.generate_str();
-                drop(bot.tox.group_message_send(bot.last_group, &message));
-                bot.trigger = false;
+        if config_watcher.changed(UTC::now().timestamp()) {
+            match Config::load(&config_watcher.path) {
+                Ok(c) => bot.reload_config(c),
+                Err(e) => warn!("Not reloading config: {}", e),
             }
         }
 
 
         /*
-            Let Lee speak every $time_interval, provided that there is given
-            permission for it
+            Let Lee speak in groupchats, when triggered or on its own.
         */
-        if bot.speak {
-            let cur_time = UTC::now().timestamp();
-            if  (bot.last_time + 10) < cur_time {
-                /* Should have only small chance to speak */
-                if 0.01 > bot.random.gen::<f64>() {
-                    let message = bot.markov.generate_str();
-                    drop(bot.tox.group_message_send(bot.last_group, &message));
-                }
+        bot.talk_in_groups();
 
-                bot.last_time = cur_time;
-            }
+
+        /*
+            Save data that has changed, once changes settle down, but no
+            later than `save_interval` seconds after the first change.
+        */
+        bot.save_if_due();
+
+
+        bot.tox.wait();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::File;
+
+    use transport::{FakeTransport, Sent};
+
+    const LEE: &'static str = "04119E835DF3E78BACF0F84235B300546AF8B936F035185E2A8E9E0A67C8924F";
+    const ALICE: &'static str = "4B2C19E924972CB9B57732FB172F8A8604DE13EEDA2A6234E348983344B23057";
+
+    fn key(k: &str) -> PublicKey {
+        k.parse().unwrap()
+    }
+
+    /*
+        Make Lee with files in a fresh directory for given test, that knows
+        only "hello there friend", is in groupchat 0 along with Alice, and
+        has Alice as friend 0.
+    */
+    fn make_bot(test: &str) -> Bot<FakeTransport> {
+        let dir = env::temp_dir().join(format!("lee-test-{}", test));
+        drop(fs::remove_dir_all(&dir));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |f: &str| dir.join(f).to_string_lossy().into_owned();
+
+        let mut config = Config::default();
+        config.tox_save = path("lee.tox");
+        config.markov_file = path("markov.json");
+        config.markov_txt = path("markov.txt");
+        config.hashes_file = path("markov.hashes");
+        config.corpus_file = path("corpus.jsonl");
+        config.ignore_file = path("ignore.txt");
+        config.group_chains_dir = path("chains");
+
+        let mut markov = Chain::for_strings();
+        markov.feed_str("hello there friend");
+
+        let mut tox = FakeTransport::new(key(LEE));
+        tox.add_peer(0, 0, "Lee", key(LEE));
+        tox.add_peer(0, 1, "Alice", key(ALICE));
+        tox.friends.insert(0, key(ALICE));
+
+        Bot::new(tox, config, markov, true)
+    }
+
+    /*
+        Take messages that were sent to friend 0, failing if anything else
+        was sent.
+    */
+    fn friend_replies(bot: &mut Bot<FakeTransport>) -> Vec<String> {
+        bot.tox.take_sent().into_iter().map(|s| match s {
+            Sent::FriendMessage(0, m) => m,
+            other => panic!("unexpected {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn group_command_is_answered_in_groupchat() {
+        let mut bot = make_bot("group-command");
+        on_group_message(&mut bot, 0, 1, ".id".to_string());
+        assert_eq!(bot.tox.take_sent(),
+                   vec![Sent::GroupMessage(0, format!("My ID: {}", key(LEE)))]);
+        assert_eq!(bot.stats.learned, 0);
+    }
+
+    #[test]
+    fn group_message_is_learned_without_reply() {
+        let mut bot = make_bot("group-learn");
+        on_group_message(&mut bot, 0, 1, "lorem ipsum dolor".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![]);
+        assert_eq!(bot.stats.learned, 1);
+        assert!(!bot.groups[&0].trigger);
+
+        // the same message isn't learned twice
+        on_group_message(&mut bot, 0, 1, "lorem ipsum dolor".to_string());
+        assert_eq!(bot.stats.learned, 1);
+    }
+
+    #[test]
+    fn mention_triggers_reply() {
+        let mut bot = make_bot("mention");
+        let msg = "what do you think, Lee?";
+        on_group_message(&mut bot, 0, 1, msg.to_string());
+        assert!(bot.groups[&0].trigger);
+        assert_eq!(bot.groups[&0].trigger_message, msg);
+        assert_eq!(bot.tox.take_sent(), vec![]);
+
+        bot.group(0).trigger_time = 0;
+        bot.talk_in_groups();
+        let sent = bot.tox.take_sent();
+        assert_eq!(sent.len(), 1);
+        match sent[0] {
+            Sent::GroupMessage(0, _) => {},
+            ref other => panic!("unexpected {:?}", other),
         }
+    }
 
+    #[test]
+    fn own_messages_are_ignored() {
+        let mut bot = make_bot("own");
+        on_group_message(&mut bot, 0, 0, ".id".to_string());
+        on_group_message(&mut bot, 0, 0, "Lee says hello".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![]);
+        assert_eq!(bot.stats.learned, 0);
+        assert!(!bot.groups[&0].trigger);
+    }
 
-        /*
-            Write save data every 64s.
+    #[test]
+    fn talk_switch_requires_moderator() {
+        let mut bot = make_bot("talk-switch");
+        on_group_message(&mut bot, 0, 1, ".stahp".to_string());
+        assert_eq!(bot.tox.take_sent(),
+                   vec![Sent::GroupMessage(0, "Sorry, `stahp` can be used only \
+                                               by moderators, and you're a \
+                                               user.".to_string())]);
+        assert!(bot.groups[&0].speak);
+
+        bot.config.permissions.moderators.push(ALICE.to_string());
+        on_group_message(&mut bot, 0, 1, ".stahp".to_string());
+        assert_eq!(bot.tox.take_sent(),
+                   vec![Sent::GroupMessage(0, "Talking on my own in this \
+                                               groupchat: off".to_string())]);
+        assert!(!bot.groups[&0].speak);
+    }
 
-            After a write, be it successful or not, set clock again to tick,
-            for the next time when it'll need to be saved.
-            TODO: save data every $relevant_event, rather than on timer.
-        */
-        let cur_time = UTC::now().timestamp();
-        if bot.last_save + 64 < cur_time {
-            match for_files::write_save("lee.tox", bot.tox.save()) {
-                Ok(_) => println!("{}: File saved.", UTC::now()),
-                Err(e) => println!("\n{}: Failed to save file: {}",
-                                UTC::now(), e),
-            }
-            drop(bot.markov.save_utf8("markov.json"));
-            println!("{}: Saved `markov.json`", UTC::now());
-            bot.last_save = cur_time;
+    #[test]
+    fn ignored_peer_gets_no_reaction() {
+        let mut bot = make_bot("ignored");
+        bot.ignored.add(ALICE).unwrap();
+        on_group_message(&mut bot, 0, 1, ".id".to_string());
+        on_group_message(&mut bot, 0, 1, "hey Lee".to_string());
+        on_friend_message(&mut bot, 0, "hi".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![]);
+        assert_eq!(bot.stats.learned, 0);
+        assert!(!bot.groups[&0].trigger);
+    }
+
+    #[test]
+    fn friend_message_is_answered() {
+        let mut bot = make_bot("friend");
+        on_friend_message(&mut bot, 0, "hi".to_string());
+        let sent = bot.tox.take_sent();
+        assert_eq!(sent.len(), 1);
+        match sent[0] {
+            Sent::FriendMessage(0, _) => {},
+            ref other => panic!("unexpected {:?}", other),
         }
+        assert_eq!(bot.stats.learned, 1);
+    }
+
+    #[test]
+    fn friend_command_is_answered_privately() {
+        let mut bot = make_bot("friend-command");
+        on_friend_message(&mut bot, 0, ".groups".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![Sent::FriendMessage(0,
+            "Groupchats:\n0: •no title• (2 peers)".to_string())]);
+        assert_eq!(bot.stats.learned, 0);
+    }
 
+    #[test]
+    fn friend_messages_are_commands_without_prefix_only_when_bare() {
+        let mut bot = make_bot("bare");
+        on_friend_message(&mut bot, 0, "invite".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![Sent::Invite(0, 0)]);
+
+        // normal messages are learned and answered, commands aren't learned
+        for (n, msg) in ["help me", "stop", "talk to me", "forget it"].iter()
+                .enumerate() {
+            on_friend_message(&mut bot, 0, msg.to_string());
+            assert_eq!(bot.stats.learned, n as u64 + 1);
+            assert_eq!(bot.tox.take_sent().len(), 1);
+        }
+    }
 
-        bot.tox.wait();
+    #[test]
+    fn chain_that_isnt_rebuildable_is_rebuilt_only_when_forced() {
+        let mut bot = make_bot("rebuild");
+        bot.config.permissions.owners.push(ALICE.to_string());
+        bot.rebuildable = false;
+
+        for msg in &[".forget hello", ".rebuild"] {
+            on_friend_message(&mut bot, 0, msg.to_string());
+            assert_eq!(friend_replies(&mut bot), vec![format!(
+                "Sorry, I couldn't {}: {}",
+                if *msg == ".rebuild" { "rebuild chains" } else { "forget that" },
+                bot.not_rebuildable())]);
+        }
+        assert_eq!(bot.markov.generate_str(), "hello there friend");
+
+        on_friend_message(&mut bot, 0, ".rebuild force".to_string());
+        let replies = friend_replies(&mut bot);
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with("Rebuilt chains"), "{:?}", replies);
+        assert!(bot.rebuildable);
+    }
+
+    #[test]
+    fn forgotten_messages_are_removed_with_backups() {
+        let mut bot = make_bot("forget");
+        bot.config.permissions.owners.push(ALICE.to_string());
+        for msg in &["a secret plan", "nothing to see", "more SECRET stuff"] {
+            bot.corpus.append(msg, corpus::Source::Friend(0), None).unwrap();
+        }
+        for file in &[&bot.config.corpus_file, &bot.config.markov_file] {
+            File::create(format!("{}.1", file)).unwrap();
+        }
+
+        on_friend_message(&mut bot, 0, ".forget secret".to_string());
+        let replies = friend_replies(&mut bot);
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with("Forgot 2 messages. Rebuilt chains from 1 \
+                                        messages"), "{:?}", replies);
+
+        let texts: Vec<String> = corpus::load(&bot.config.corpus_file, 0).unwrap()
+            .into_iter().map(|e| e.text).collect();
+        assert_eq!(texts, vec!["nothing to see".to_string()]);
+        for file in &[&bot.config.corpus_file, &bot.config.markov_file] {
+            assert!(fs::metadata(file).is_ok());
+            assert!(fs::metadata(format!("{}.1", file)).is_err());
+        }
+        assert_eq!(bot.markov.generate_str(), "nothing to see");
+    }
+
+    #[test]
+    fn impostor_is_warned_about_unless_ignored() {
+        const BOB: &'static str = "788236D34978D1D5BD822F0A5BEBD2C53C64CC31CD3149350EE27D4D9A2F9B6B";
+        let mut bot = make_bot("impostor");
+        bot.tox.add_peer(0, 2, "Lее", key(BOB));
+        on_group_namelist_change(&mut bot, 0, 2, ChatChange::PeerName);
+        assert_eq!(bot.tox.take_sent(),
+                   vec![Sent::GroupMessage(0, "Lее is an impostor!".to_string())]);
+
+        let mut bot = make_bot("impostor-ignored");
+        bot.tox.add_peer(0, 2, "Lее", key(BOB));
+        bot.ignored.add(BOB).unwrap();
+        on_group_namelist_change(&mut bot, 0, 2, ChatChange::PeerName);
+        on_group_message(&mut bot, 0, 2, "I'm Lee".to_string());
+        assert_eq!(bot.tox.take_sent(), vec![]);
+    }
+
+    #[test]
+    fn long_ignore_list_is_split() {
+        let mut bot = make_bot("ignore-list");
+        bot.config.permissions.owners.push(ALICE.to_string());
+        for n in 0..30 {
+            bot.ignored.add(&format!("{:064X}", n)).unwrap();
+        }
+        on_friend_message(&mut bot, 0, ".ignore".to_string());
+        let replies = friend_replies(&mut bot);
+        assert_eq!(replies.len(), 2);
+        assert!(replies.iter().all(|m| m.len() <= transport::MAX_MESSAGE_LENGTH));
+        assert_eq!(replies.join("\n").lines().count(), 31);
+    }
+
+    #[test]
+    fn empty_groupchat_is_left() {
+        let mut bot = make_bot("leave");
+        on_group_message(&mut bot, 0, 1, "bye".to_string());
+        bot.tox.remove_peer(0, 1);
+        on_group_namelist_change(&mut bot, 0, 1, ChatChange::PeerDel);
+        assert_eq!(bot.tox.take_sent(), vec![Sent::Left(0)]);
+        assert!(!bot.groups.contains_key(&0));
     }
 }
@@ -0,0 +1,261 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Parser of plaintext file with messages that markov chain is fed with
+    when there's no saved chain, e.g. `markov.txt`.
+
+    Format:
+
+     - every line is a separate message,
+     - empty lines are skipped,
+     - lines starting with `//` are comments,
+     - `/*` at the start of a line starts a comment that lasts until `*/`,
+       which can be on the same or on a later line; text after the end of
+       comment is a message. If nothing after it closes the comment, only
+       that line is a comment, as it used to be before comments could span
+       many lines,
+     - line ending with `\` is joined with the next line, with a newline
+       between them,
+     - `\\` is a single `\`, thus line ending with `\\` isn't joined with
+       the next one.
+*/
+
+
+/**
+    Message parsed from a file.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /**
+        Number of line on which message starts, counting from 1.
+    */
+    pub line: usize,
+
+    pub text: String,
+}
+
+
+/**
+    Problem found while parsing. Parsing doesn't stop on it.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /**
+        Number of line that problem is about, counting from 1.
+    */
+    pub line: usize,
+
+    pub message: String,
+}
+
+
+/*
+    Unescape `\\` in a line, and check whether it ends with unescaped `\`.
+*/
+fn unescape(line: &str) -> (String, bool) {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => text.push('\\'),
+            Some(other) => {
+                text.push('\\');
+                text.push(other);
+            },
+            None => return (text, true),
+        }
+    }
+    (text, false)
+}
+
+
+/**
+    Parse content of a file, returning messages in order in which they are
+    in the file, and problems that were found.
+*/
+pub fn parse(content: &str) -> (Vec<Entry>, Vec<Diagnostic>) {
+    let mut entries = vec![];
+    let mut diagnostics = vec![];
+
+    // message that is being continued, and line on which it started
+    let mut pending: Option<Entry> = None;
+    // whether block comment is being continued
+    let mut comment = false;
+
+    // index of the last line that could close a block comment
+    let lines: Vec<&str> = content.lines().collect();
+    let last_close = lines.iter().rposition(|l| l.contains("*/"));
+
+    for (n, &line) in lines.iter().enumerate() {
+        let n = n + 1;
+        let mut line = line;
+
+        if comment {
+            match line.find("*/") {
+                Some(end) => {
+                    comment = false;
+                    line = &line[end + 2..];
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                },
+                None => continue,
+            }
+        } else if pending.is_none() {
+            if line.starts_with("//") {
+                continue;
+            }
+            if line.starts_with("/*") {
+                match line[2..].find("*/") {
+                    Some(end) => {
+                        line = &line[end + 4..];
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                    },
+                    // line with index `n` is the one after this one
+                    None if last_close.map(|c| c >= n).unwrap_or(false) => {
+                        comment = true;
+                        continue;
+                    },
+                    None => {
+                        diagnostics.push(Diagnostic {
+                            line: n,
+                            message: "comment isn't closed with `*/`, \
+                                      treating it as a one-line comment"
+                                      .to_string(),
+                        });
+                        continue;
+                    },
+                }
+            }
+        }
+
+        if line.trim().is_empty() {
+            if let Some(entry) = pending.take() {
+                diagnostics.push(Diagnostic {
+                    line: n,
+                    message: format!("empty line after continued line, \
+                                      message started on line {} ends here",
+                                     entry.line),
+                });
+                entries.push(entry);
+            }
+            continue;
+        }
+
+        let (text, continued) = unescape(line);
+        let mut entry = match pending.take() {
+            Some(mut entry) => {
+                entry.text.push('\n');
+                entry.text.push_str(&text);
+                entry
+            },
+            None => Entry { line: n, text: text },
+        };
+
+        if continued {
+            pending = Some(entry);
+        } else {
+            entry.text = entry.text.trim_right().to_string();
+            if entry.text.is_empty() {
+                diagnostics.push(Diagnostic {
+                    line: entry.line,
+                    message: "message is empty, skipping".to_string(),
+                });
+            } else {
+                entries.push(entry);
+            }
+        }
+    }
+
+    if let Some(entry) = pending {
+        diagnostics.push(Diagnostic {
+            line: entry.line,
+            message: "file ends with continued line".to_string(),
+        });
+        entries.push(entry);
+    }
+
+    (entries, diagnostics)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(entries: &[Entry]) -> Vec<(usize, &str)> {
+        entries.iter().map(|e| (e.line, &e.text[..])).collect()
+    }
+
+    #[test]
+    fn lines_are_messages() {
+        let (entries, diagnostics) = parse("hello\n\n// comment\nworld  \n");
+        assert_eq!(texts(&entries), vec![(1, "hello"), (4, "world")]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn block_comments_are_skipped() {
+        let content = "/* one line */\n/* many\nlines */after\n/* */\nlast";
+        let (entries, diagnostics) = parse(content);
+        assert_eq!(texts(&entries), vec![(3, "after"), (5, "last")]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unclosed_comment_is_one_line() {
+        let content = "/* many\nlines\n*/\n/* not closed\nmessage";
+        let (entries, diagnostics) = parse(content);
+        assert_eq!(texts(&entries), vec![(5, "message")]);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![4]);
+    }
+
+    #[test]
+    fn comment_markers_inside_messages_are_text() {
+        let (entries, _) = parse("a // b\na /* b");
+        assert_eq!(texts(&entries), vec![(1, "a // b"), (2, "a /* b")]);
+    }
+
+    #[test]
+    fn continued_lines_are_joined() {
+        let content = "first \\\nsecond\nthird\\\\\nfourth";
+        let (entries, diagnostics) = parse(content);
+        assert_eq!(texts(&entries),
+                   vec![(1, "first \nsecond"), (3, "third\\"), (4, "fourth")]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn problems_have_line_numbers() {
+        let content = "ok\nopen \\\n\n/* not closed\nstill a message";
+        let (entries, diagnostics) = parse(content);
+        assert_eq!(texts(&entries),
+                   vec![(1, "ok"), (2, "open "), (5, "still a message")]);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![3, 4]);
+    }
+}
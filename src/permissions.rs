@@ -0,0 +1,169 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Roles of peers, and permissions required to use commands.
+
+    Roles are tied to public keys, thus messages relayed by bridges, like
+    the IRC one, are checked against public key of the bridge, rather than
+    against whatever name relayed user has.
+*/
+
+use std::collections::HashMap;
+use std::fmt;
+
+use rstox::core::PublicKey;
+
+
+/**
+    Role of a peer. Every role can do everything that roles below it can.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    User,
+    Moderator,
+    Owner,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "user" => Some(Role::User),
+            "moderator" => Some(Role::Moderator),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+/**
+    Names of commands for which permissions can be set, without prefix.
+*/
+pub const COMMANDS: &'static [&'static str] = &[
+    "about",
+    "forget",
+    "groups",
+    "help",
+    "id",
+    "ignore",
+    "invite",
+    "rebuild",
+    "say",
+    "stats",
+    "stahp",
+    "talk",
+    "unignore",
+];
+
+
+/**
+    Roles required to use commands, unless set otherwise in config.
+    Commands that aren't there can be used by everyone.
+*/
+const DEFAULTS: &'static [(&'static str, Role)] = &[
+    ("forget", Role::Owner),
+    ("ignore", Role::Moderator),
+    ("rebuild", Role::Owner),
+    ("stahp", Role::Moderator),
+    ("talk", Role::Moderator),
+    ("unignore", Role::Moderator),
+];
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Permissions {
+    /**
+        Uppercase public keys of owners.
+    */
+    pub owners: Vec<String>,
+
+    /**
+        Uppercase public keys of moderators.
+    */
+    pub moderators: Vec<String>,
+
+    /**
+        Role required to use a command. Commands that aren't there use
+        defaults.
+    */
+    pub commands: HashMap<String, Role>,
+}
+
+
+impl Permissions {
+    /**
+        Get role of peer with given public key.
+    */
+    pub fn role(&self, pk: &PublicKey) -> Role {
+        self.role_of_key(&format!("{}", pk).to_uppercase())
+    }
+
+    /**
+        Get role of peer with given uppercase public key.
+    */
+    pub fn role_of_key(&self, pk: &str) -> Role {
+        let pk = pk.to_string();
+        if self.owners.contains(&pk) {
+            Role::Owner
+        } else if self.moderators.contains(&pk) {
+            Role::Moderator
+        } else {
+            Role::User
+        }
+    }
+
+    /**
+        Get role required to use a command.
+    */
+    pub fn required(&self, command: &str) -> Role {
+        match self.commands.get(command) {
+            Some(role) => *role,
+            None => DEFAULTS.iter().find(|&&(c, _)| c == command)
+                        .map(|&(_, role)| role).unwrap_or(Role::User),
+        }
+    }
+
+    /**
+        Check whether peer with given public key can use a command.
+
+        If it can't, return message refusing it, that should be sent back.
+    */
+    pub fn check(&self, pk: &PublicKey, command: &str) -> Result<(), String> {
+        let role = self.role(pk);
+        let required = self.required(command);
+        if role >= required {
+            Ok(())
+        } else {
+            Err(format!("Sorry, `{}` can be used only by {}s, and you're a {}.",
+                        command, required, role))
+        }
+    }
+}
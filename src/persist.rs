@@ -0,0 +1,138 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Deciding when data should be saved.
+
+    Instead of saving everything on timer, changes are marked as they
+    happen, and data is saved only when something has changed:
+
+     - once there were no new changes for `debounce` seconds, so that burst
+       of changes would result in a single save,
+     - but no later than `max_delay` seconds after first unsaved change,
+       so that constant stream of changes wouldn't postpone saving forever,
+     - or right away, if change was important enough to be forced.
+*/
+
+
+/**
+    Data that can be saved.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Data {
+    /**
+        Tox save file – friends, name, status message.
+    */
+    Tox,
+
+    /**
+        Markov chain, along with hashes of learned messages.
+    */
+    Chain,
+}
+
+
+/**
+    What should be saved.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dirty {
+    pub tox: bool,
+    pub chain: bool,
+}
+
+
+pub struct Persist {
+    dirty: Dirty,
+    forced: bool,
+
+    /**
+        Time of first change since last save.
+    */
+    first_change: i64,
+
+    /**
+        Time of the most recent change.
+    */
+    last_change: i64,
+
+    debounce: i64,
+    max_delay: i64,
+}
+
+
+impl Persist {
+    pub fn new(debounce: i64, max_delay: i64) -> Persist {
+        Persist {
+            dirty: Dirty::default(),
+            forced: false,
+            first_change: 0,
+            last_change: 0,
+            debounce: debounce,
+            max_delay: max_delay,
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty.tox || self.dirty.chain
+    }
+
+    /**
+        Mark data as changed at `cur_time`.
+    */
+    pub fn mark(&mut self, data: Data, cur_time: i64) {
+        if !self.is_dirty() {
+            self.first_change = cur_time;
+        }
+        self.last_change = cur_time;
+
+        match data {
+            Data::Tox => self.dirty.tox = true,
+            Data::Chain => self.dirty.chain = true,
+        }
+    }
+
+    /**
+        Mark data as changed, and make it saved as soon as possible.
+    */
+    pub fn force(&mut self, data: Data, cur_time: i64) {
+        self.mark(data, cur_time);
+        self.forced = true;
+    }
+
+    /**
+        Check whether it's time to save, and if it is, return what should be
+        saved, assuming that it will be.
+    */
+    pub fn take_due(&mut self, cur_time: i64) -> Option<Dirty> {
+        if !self.is_dirty() {
+            return None;
+        }
+
+        if self.forced
+            || self.last_change + self.debounce <= cur_time
+            || self.first_change + self.max_delay <= cur_time {
+            let dirty = self.dirty;
+            self.dirty = Dirty::default();
+            self.forced = false;
+            return Some(dirty);
+        }
+        None
+    }
+}
@@ -0,0 +1,397 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Rate limiting, so that a single peer couldn't make Lee reply over and
+    over, and Lee wouldn't flood a groupchat.
+
+    Every kind of action has token buckets – one per public key of peer,
+    and, for actions in groupchats, one per groupchat. Every action takes
+    a token, and tokens are refilled with time, up to bucket's capacity.
+    Peer who runs out of tokens is ignored for a cooldown period.
+*/
+
+use std::collections::HashMap;
+
+
+/**
+    Size of a bucket, and how fast it's refilled. Bucket of size `0` never
+    runs out, i.e. there's no limit.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub burst: f64,
+    pub per_minute: f64,
+}
+
+impl Quota {
+    pub fn new(burst: f64, per_minute: f64) -> Quota {
+        Quota {
+            burst: burst,
+            per_minute: per_minute,
+        }
+    }
+
+    fn unlimited(&self) -> bool {
+        self.burst <= 0.0
+    }
+}
+
+
+/**
+    Rate limiting configuration.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitConfig {
+    /**
+        Time for which peer who exceeded a quota is ignored, in seconds.
+    */
+    pub cooldown: i64,
+
+    pub peer_triggers: Quota,
+    pub peer_commands: Quota,
+    pub friend_replies: Quota,
+    pub group_triggers: Quota,
+    pub group_commands: Quota,
+}
+
+impl Default for LimitConfig {
+    fn default() -> LimitConfig {
+        LimitConfig {
+            cooldown: 300,
+            peer_triggers: Quota::new(3.0, 2.0),
+            peer_commands: Quota::new(5.0, 5.0),
+            friend_replies: Quota::new(10.0, 10.0),
+            group_triggers: Quota::new(6.0, 4.0),
+            group_commands: Quota::new(10.0, 10.0),
+        }
+    }
+}
+
+
+/**
+    Kind of action that is limited.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Trigger,
+    Command,
+    FriendReply,
+}
+
+
+/**
+    Whether action can be done.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+
+    /**
+        Groupchat has run out of tokens.
+    */
+    Limited,
+
+    /**
+        Peer has run out of tokens just now, and cooldown has started.
+    */
+    CooldownStarted,
+
+    /**
+        Peer is in cooldown.
+    */
+    CoolingDown,
+}
+
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    last: i64,
+}
+
+impl Bucket {
+    fn new(quota: &Quota, cur_time: i64) -> Bucket {
+        Bucket {
+            tokens: quota.burst,
+            last: cur_time,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, cur_time: i64) {
+        let elapsed = (cur_time - self.last) as f64;
+        self.tokens = (self.tokens + elapsed * quota.per_minute / 60.0)
+                          .min(quota.burst);
+        self.last = cur_time;
+    }
+
+    /**
+        Take a token, if there's one.
+    */
+    fn take(&mut self, quota: &Quota, cur_time: i64) -> bool {
+        self.refill(quota, cur_time);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+
+/*
+    When there are more buckets than that, full ones are removed – and the
+    same for cooldowns that have ended.
+*/
+const MAX_BUCKETS: usize = 1024;
+
+
+pub struct Limits {
+    pub config: LimitConfig,
+    peers: HashMap<(String, Action), Bucket>,
+    groups: HashMap<(i32, Action), Bucket>,
+
+    /**
+        Time until which peers are ignored, by public key. Cooldowns that
+        have ended are removed when peer is checked again, or when there
+        are too many of them.
+    */
+    cooldowns: HashMap<String, i64>,
+}
+
+
+impl Limits {
+    pub fn new(config: LimitConfig) -> Limits {
+        Limits {
+            config: config,
+            peers: HashMap::new(),
+            groups: HashMap::new(),
+            cooldowns: HashMap::new(),
+        }
+    }
+
+    fn peer_quota(&self, action: Action) -> Quota {
+        match action {
+            Action::Trigger => self.config.peer_triggers,
+            Action::Command => self.config.peer_commands,
+            Action::FriendReply => self.config.friend_replies,
+        }
+    }
+
+    fn group_quota(&self, action: Action) -> Option<Quota> {
+        match action {
+            Action::Trigger => Some(self.config.group_triggers),
+            Action::Command => Some(self.config.group_commands),
+            Action::FriendReply => None,
+        }
+    }
+
+    /**
+        Check whether peer is ignored, because it's in cooldown.
+    */
+    pub fn cooling_down(&mut self, pk: &str, cur_time: i64) -> bool {
+        match self.cooldowns.get(pk).cloned() {
+            Some(until) if until > cur_time => true,
+            Some(_) => {
+                self.cooldowns.remove(pk);
+                false
+            },
+            None => false,
+        }
+    }
+
+    /**
+        Check whether peer with public key `pk` can do `action`, in
+        groupchat `group`, if it's done in one, and if it can, take tokens
+        for it.
+    */
+    pub fn check(&mut self, action: Action, pk: &str, group: Option<i32>,
+                 cur_time: i64) -> Verdict {
+        if self.cooling_down(pk, cur_time) {
+            return Verdict::CoolingDown;
+        }
+        self.prune(cur_time);
+
+        let quota = self.peer_quota(action);
+        if !quota.unlimited() {
+            let bucket = self.peers.entry((pk.to_string(), action))
+                .or_insert_with(|| Bucket::new(&quota, cur_time));
+            if !bucket.take(&quota, cur_time) {
+                let until = cur_time + self.config.cooldown;
+                self.cooldowns.insert(pk.to_string(), until);
+                return Verdict::CooldownStarted;
+            }
+        }
+
+        if let (Some(gnum), Some(quota)) = (group, self.group_quota(action)) {
+            if !quota.unlimited() {
+                let bucket = self.groups.entry((gnum, action))
+                    .or_insert_with(|| Bucket::new(&quota, cur_time));
+                if !bucket.take(&quota, cur_time) {
+                    return Verdict::Limited;
+                }
+            }
+        }
+
+        Verdict::Allowed
+    }
+
+    /*
+        Remove buckets that would be full by now, if there are too many of
+        them, since they're the same as new ones. Cooldowns that have ended
+        are removed too, if there are too many of them.
+    */
+    fn prune(&mut self, cur_time: i64) {
+        if self.cooldowns.len() > MAX_BUCKETS {
+            let ended: Vec<String> = self.cooldowns.iter()
+                .filter(|&(_, until)| *until <= cur_time)
+                .map(|(k, _)| k.clone()).collect();
+            for k in ended {
+                self.cooldowns.remove(&k);
+            }
+        }
+
+        if self.peers.len() > MAX_BUCKETS {
+            let full: Vec<(String, Action)> = self.peers.iter()
+                .filter(|&(k, b)| {
+                    let quota = self.peer_quota(k.1);
+                    let mut b = *b;
+                    b.refill(&quota, cur_time);
+                    b.tokens >= quota.burst
+                })
+                .map(|(k, _)| k.clone()).collect();
+            for k in full {
+                self.peers.remove(&k);
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PK: &'static str = "951C88B7E75C867418ACDB5D273821372BB5BD652740BCDF623A4FA293E75D2F";
+
+    /*
+        Limits where everything is unlimited, until changed.
+    */
+    fn unlimited() -> LimitConfig {
+        LimitConfig {
+            cooldown: 60,
+            peer_triggers: Quota::new(0.0, 0.0),
+            peer_commands: Quota::new(0.0, 0.0),
+            friend_replies: Quota::new(0.0, 0.0),
+            group_triggers: Quota::new(0.0, 0.0),
+            group_commands: Quota::new(0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn burst_of_zero_is_unlimited() {
+        let mut limits = Limits::new(unlimited());
+        for t in 0..1000 {
+            for &action in &[Action::Trigger, Action::Command, Action::FriendReply] {
+                assert_eq!(limits.check(action, PK, Some(0), t / 100),
+                           Verdict::Allowed);
+            }
+        }
+        assert!(limits.peers.is_empty() && limits.groups.is_empty());
+    }
+
+    #[test]
+    fn bucket_is_refilled_over_time() {
+        // a token every 10 seconds, up to 2
+        let mut limits = Limits::new(LimitConfig {
+            group_commands: Quota::new(2.0, 6.0),
+            ..unlimited()
+        });
+        let mut check = |t| limits.check(Action::Command, PK, Some(0), t);
+
+        assert_eq!(check(0), Verdict::Allowed);
+        assert_eq!(check(0), Verdict::Allowed);
+        assert_eq!(check(0), Verdict::Limited);
+        assert_eq!(check(5), Verdict::Limited);
+        assert_eq!(check(10), Verdict::Allowed);
+        assert_eq!(check(10), Verdict::Limited);
+        // no more than burst after a long time
+        assert_eq!(check(1000), Verdict::Allowed);
+        assert_eq!(check(1000), Verdict::Allowed);
+        assert_eq!(check(1000), Verdict::Limited);
+    }
+
+    #[test]
+    fn groups_and_actions_have_separate_buckets() {
+        let mut limits = Limits::new(LimitConfig {
+            group_triggers: Quota::new(1.0, 1.0),
+            ..unlimited()
+        });
+        assert_eq!(limits.check(Action::Trigger, PK, Some(0), 0), Verdict::Allowed);
+        assert_eq!(limits.check(Action::Trigger, PK, Some(0), 0), Verdict::Limited);
+        assert_eq!(limits.check(Action::Trigger, PK, Some(1), 0), Verdict::Allowed);
+        assert_eq!(limits.check(Action::Command, PK, Some(0), 0), Verdict::Allowed);
+        assert_eq!(limits.check(Action::Trigger, PK, None, 0), Verdict::Allowed);
+    }
+
+    #[test]
+    fn cooldown_ends() {
+        let mut limits = Limits::new(LimitConfig {
+            peer_triggers: Quota::new(1.0, 60.0),
+            ..unlimited()
+        });
+        assert_eq!(limits.check(Action::Trigger, PK, None, 0), Verdict::Allowed);
+        assert_eq!(limits.check(Action::Trigger, PK, None, 0),
+                   Verdict::CooldownStarted);
+        // cooldown covers every action
+        assert_eq!(limits.check(Action::Command, PK, None, 1), Verdict::CoolingDown);
+        assert!(limits.cooling_down(PK, 59));
+        assert_eq!(limits.check(Action::Trigger, PK, None, 59), Verdict::CoolingDown);
+
+        assert!(!limits.cooling_down(PK, 60));
+        assert!(limits.cooldowns.is_empty());
+        assert_eq!(limits.check(Action::Trigger, PK, None, 60), Verdict::Allowed);
+    }
+
+    #[test]
+    fn ended_cooldowns_and_full_buckets_are_pruned() {
+        let mut limits = Limits::new(LimitConfig {
+            peer_triggers: Quota::new(1.0, 60.0),
+            ..unlimited()
+        });
+        for n in 0..MAX_BUCKETS + 1 {
+            let pk = format!("{:064X}", n);
+            assert_eq!(limits.check(Action::Trigger, &pk, None, 0), Verdict::Allowed);
+            assert_eq!(limits.check(Action::Trigger, &pk, None, 0),
+                       Verdict::CooldownStarted);
+        }
+        assert_eq!(limits.cooldowns.len(), MAX_BUCKETS + 1);
+
+        // buckets are full again, but cooldowns haven't ended yet
+        assert_eq!(limits.check(Action::Trigger, PK, None, 30), Verdict::Allowed);
+        assert_eq!(limits.cooldowns.len(), MAX_BUCKETS + 1);
+        assert_eq!(limits.peers.len(), 1);
+
+        assert_eq!(limits.check(Action::Trigger, PK, None, 60), Verdict::Allowed);
+        assert!(limits.cooldowns.is_empty());
+        assert_eq!(limits.peers.len(), 1);
+    }
+}
@@ -0,0 +1,132 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Generating replies seeded from a message that Lee is replying to, so
+    that reply would have at least something to do with it.
+
+    Markov chain can start generating only from a word that it already
+    knows, thus words from the message are tried one by one, starting
+    with the most salient ones, until one of them works.
+*/
+
+use markov::Chain;
+
+use rand::Rng;
+
+
+/*
+    Words that are too common to say anything about what message is about.
+*/
+const STOP_WORDS: &'static [&'static str] = &[
+    "about", "after", "again", "all", "also", "and", "any", "are", "because",
+    "been", "but", "can", "could", "did", "does", "for", "from", "get",
+    "got", "had", "has", "have", "her", "here", "him", "his", "how", "its",
+    "just", "like", "not", "now", "one", "only", "our", "out", "she", "some",
+    "than", "that", "the", "their", "them", "then", "there", "these",
+    "they", "this", "too", "very", "was", "were", "what", "when", "where",
+    "which", "who", "why", "will", "with", "would", "you", "your",
+];
+
+
+/*
+    Strip punctuation around the word.
+*/
+fn trim_word(word: &str) -> &str {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+}
+
+
+/**
+    Get words from a message that can be used as seeds, the most salient
+    first.
+
+    Lee's `names`, i.e. name and aliases, stop words and words shorter than 3 characters are
+    skipped. Longer words are considered more salient, and words of the
+    same length are in random order. Since chain knows words along with
+    punctuation, both the word as it is and the word without punctuation
+    are included.
+*/
+pub fn salient_words<R: Rng>(message: &str, names: &[String], rng: &mut R)
+    -> Vec<String>
+{
+    let names: Vec<String> = names.iter()
+        .filter(|n| !n.is_empty())
+        .map(|n| n.to_lowercase())
+        .collect();
+    let mut words: Vec<&str> = message.split_whitespace().filter(|w| {
+        let trimmed = trim_word(w).to_lowercase();
+        trimmed.chars().count() >= 3
+            && !names.iter().any(|n| trimmed.contains(&**n))
+            && !STOP_WORDS.contains(&&*trimmed)
+    }).collect();
+
+    rng.shuffle(&mut words);
+    words.sort_by(|a, b| trim_word(b).len().cmp(&trim_word(a).len()));
+
+    let mut seeds: Vec<String> = vec![];
+    for word in words {
+        for seed in &[word, trim_word(word)] {
+            if !seeds.iter().any(|s| &s[..] == *seed) {
+                seeds.push(seed.to_string());
+            }
+        }
+    }
+    seeds
+}
+
+
+/**
+    Generate sentence starting from the first of `seeds` that chain knows.
+
+    Returns `None` if chain doesn't know any of them.
+*/
+pub fn generate_from(chain: &Chain<String>, seeds: &[String]) -> Option<String> {
+    seeds.iter()
+        .map(|seed| chain.generate_str_from_token(seed))
+        .find(|sentence| !sentence.is_empty())
+}
+
+
+/**
+    Generate reply to a message, seeded from its salient words, or a
+    random sentence if chain doesn't know any of them.
+*/
+pub fn reply_to<R: Rng>(chain: &Chain<String>, message: &str,
+                        names: &[String], rng: &mut R) -> String {
+    let seeds = salient_words(message, names, rng);
+    generate_from(chain, &seeds).unwrap_or_else(|| chain.generate_str())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand;
+
+    #[test]
+    fn name_aliases_and_short_words_are_not_seeds() {
+        let mut rng = rand::thread_rng();
+        let names = vec!["lee".to_string(), "ли".to_string(), "".to_string()];
+        let seeds = salient_words("Hey Lee, ЛИ and Leeroy: tell me about \
+                                   squirrels!", &names, &mut rng);
+        assert_eq!(seeds, vec!["squirrels!", "squirrels", "tell", "Hey"]);
+    }
+}
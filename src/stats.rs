@@ -0,0 +1,187 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Statistics about Lee – how much it has learned, and how it's doing.
+*/
+
+use markov::Chain;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json::{self, Json};
+
+
+/**
+    Counters that Lee updates while running.
+*/
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /**
+        UNIX time when Lee was started.
+    */
+    pub started: i64,
+
+    /**
+        Messages learned since Lee was started.
+    */
+    pub learned: u64,
+
+    /**
+        Messages learned before Lee was started, i.e. in the corpus.
+    */
+    pub learned_before: u64,
+
+    /**
+        UNIX time of the last successful save, of any data.
+    */
+    pub last_save: Option<i64>,
+
+    /**
+        Number of states and number of distinct tokens in the global chain,
+        as of when it was last loaded or saved, if known.
+    */
+    pub chain_size: Option<(usize, usize)>,
+}
+
+impl Stats {
+    pub fn new(started: i64, learned_before: u64,
+               chain_size: Option<(usize, usize)>) -> Stats {
+        Stats {
+            started: started,
+            learned: 0,
+            learned_before: learned_before,
+            last_save: None,
+            chain_size: chain_size,
+        }
+    }
+}
+
+
+/**
+    Things that Lee doesn't keep count of itself, and which have to be
+    supplied when making a report.
+*/
+pub struct Counts {
+    pub hashes: usize,
+    pub friends: usize,
+    pub groups: usize,
+}
+
+
+/**
+    Get number of states and number of distinct tokens in a chain, encoded
+    in the same way as when it's saved.
+
+    Chain doesn't expose its internals, thus they have to be counted in
+    the encoded chain – which for a big chain isn't exactly cheap, thus it
+    should be done only when chain is encoded anyway, i.e. when it's saved.
+    Returns `None` if that fails.
+*/
+pub fn chain_size(encoded: &str) -> Option<(usize, usize)> {
+    let decoded = match Json::from_str(encoded) {
+        Ok(d) => d,
+        Err(_) => return None,
+    };
+
+    /*
+        Map from state to possible next tokens, each of them along with
+        its weight.
+    */
+    let map = match decoded.find("map") {
+        Some(m) => m,
+        None => return None,
+    };
+    let nexts: Vec<&Json> = match *map {
+        Json::Object(ref o) => o.values().collect(),
+        Json::Array(ref a) => a.iter().filter_map(|pair| {
+            pair.as_array().and_then(|p| p.get(1))
+        }).collect(),
+        _ => return None,
+    };
+
+    let mut tokens: Vec<String> = vec![];
+    for next in &nexts {
+        let keys: Vec<String> = match **next {
+            Json::Object(ref o) => o.keys().cloned().collect(),
+            Json::Array(ref a) => a.iter().filter_map(|pair| {
+                pair.as_array().and_then(|p| p.get(0)).map(|t| t.to_string())
+            }).collect(),
+            _ => continue,
+        };
+        tokens.extend(keys);
+    }
+    tokens.sort();
+    tokens.dedup();
+
+    Some((nexts.len(), tokens.len()))
+}
+
+
+/**
+    Get number of states and number of distinct tokens in a chain that
+    isn't going to be saved soon, e.g. one that was just loaded.
+*/
+pub fn measure(chain: &Chain<String>) -> Option<(usize, usize)> {
+    json::encode(chain).ok().and_then(|e| chain_size(&e))
+}
+
+
+/**
+    Format duration in seconds as e.g. `2d 3h 5m`.
+*/
+pub fn format_duration(secs: i64) -> String {
+    let secs = if secs < 0 { 0 } else { secs };
+    let (days, hours, mins) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m", mins)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+
+/**
+    Make report, as separate lines, so that they could be joined in
+    a way that fits where report goes.
+*/
+pub fn report(stats: &Stats, counts: &Counts, cur_time: i64) -> Vec<String> {
+    let chain = match stats.chain_size {
+        Some((states, tokens)) =>
+            format!("Chain: {} states, {} tokens", states, tokens),
+        None => "Chain: size unknown".to_string(),
+    };
+    let last_save = match stats.last_save {
+        Some(t) => format!("{} ago", format_duration(cur_time - t)),
+        None => "not yet".to_string(),
+    };
+
+    vec![
+        chain,
+        format!("Learned: {} messages this session, {} in total",
+                stats.learned, stats.learned_before + stats.learned),
+        format!("Remembered hashes: {}", counts.hashes),
+        format!("Friends: {}, groupchats: {}", counts.friends, counts.groups),
+        format!("Uptime: {}", format_duration(cur_time - stats.started)),
+        format!("Last save: {}", last_save),
+    ]
+}
@@ -0,0 +1,399 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Operations on Tox network that Lee is using.
+
+    Bot logic should go only through `Transport`, rather than through `Tox`
+    directly, so that it could be run with something else than real Tox
+    instance – e.g. with `FakeTransport`, which only records what Lee would
+    have sent.
+
+    Things that are needed only by main loop, like iterating over events,
+    bootstrapping or saving, are not a part of it.
+*/
+
+#[cfg(test)]
+use std::collections::HashMap;
+
+use rstox::core::*;
+
+
+/**
+    Max length of a message that Tox can send, in bytes.
+*/
+pub const MAX_MESSAGE_LENGTH: usize = 1372;
+
+
+/**
+    Join lines into as few messages as possible, none of them longer than
+    `MAX_MESSAGE_LENGTH`. Lines aren't split, thus line that is too long
+    on its own makes a message that is too long too.
+*/
+pub fn join_lines(lines: &[String]) -> Vec<String> {
+    let mut messages: Vec<String> = vec![];
+    for line in lines {
+        if let Some(last) = messages.last_mut() {
+            if last.len() + 1 + line.len() <= MAX_MESSAGE_LENGTH {
+                last.push('\n');
+                last.push_str(line);
+                continue;
+            }
+        }
+        messages.push(line.clone());
+    }
+    messages
+}
+
+
+/**
+    Place from which message came, and to which reply should be sent.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chat {
+    Friend(u32),
+    Group(i32),
+}
+
+
+pub trait Transport {
+    /**
+        Own public key.
+    */
+    fn public_key(&self) -> PublicKey;
+
+    /**
+        Own Tox ID, formatted for displaying.
+    */
+    fn address(&self) -> String;
+
+    fn set_status_message(&mut self, message: &str) -> Result<(), String>;
+
+    /**
+        Add friend without sending a friend request, i.e. accept friend
+        request. Returns friend number.
+    */
+    fn add_friend(&mut self, pk: &PublicKey) -> Result<u32, String>;
+
+    fn friend_public_key(&self, fnum: u32) -> Option<PublicKey>;
+
+    fn friend_count(&self) -> usize;
+
+    fn send_friend_message(&mut self, fnum: u32, message: &str)
+        -> Result<(), String>;
+
+    fn send_group_message(&mut self, gnum: i32, message: &str)
+        -> Result<(), String>;
+
+    fn group_peername(&self, gnum: i32, pnum: i32) -> Option<String>;
+
+    fn group_peer_pubkey(&self, gnum: i32, pnum: i32) -> Option<PublicKey>;
+
+    fn group_number_peers(&self, gnum: i32) -> Option<i32>;
+
+    /**
+        Numbers of all groupchats that Lee is in.
+    */
+    fn group_list(&self) -> Vec<i32>;
+
+    fn group_title(&self, gnum: i32) -> Option<String>;
+
+    fn invite_friend(&mut self, fnum: u32, gnum: i32) -> Result<(), String>;
+
+    /**
+        Join groupchat to which friend `fnum` invited. Returns group number.
+    */
+    fn join_group(&mut self, fnum: i32, data: &[u8]) -> Result<i32, String>;
+
+    fn leave_group(&mut self, gnum: i32) -> Result<(), String>;
+
+    /**
+        Send message to a friend or a groupchat, depending on `chat`.
+    */
+    fn send_message(&mut self, chat: Chat, message: &str) -> Result<(), String> {
+        match chat {
+            Chat::Friend(fnum) => self.send_friend_message(fnum, message),
+            Chat::Group(gnum) => self.send_group_message(gnum, message),
+        }
+    }
+}
+
+
+impl Transport for Tox {
+    fn public_key(&self) -> PublicKey {
+        self.get_public_key()
+    }
+
+    fn address(&self) -> String {
+        format!("{}", self.get_address())
+    }
+
+    fn set_status_message(&mut self, message: &str) -> Result<(), String> {
+        Tox::set_status_message(self, message)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn add_friend(&mut self, pk: &PublicKey) -> Result<u32, String> {
+        self.add_friend_norequest(pk).map_err(|e| format!("{:?}", e))
+    }
+
+    fn friend_public_key(&self, fnum: u32) -> Option<PublicKey> {
+        self.get_friend_public_key(fnum)
+    }
+
+    fn friend_count(&self) -> usize {
+        self.get_friend_list().len()
+    }
+
+    fn send_friend_message(&mut self, fnum: u32, message: &str)
+        -> Result<(), String>
+    {
+        Tox::send_friend_message(self, fnum, MessageType::Normal, message)
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn send_group_message(&mut self, gnum: i32, message: &str)
+        -> Result<(), String>
+    {
+        self.group_message_send(gnum, message)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn group_peername(&self, gnum: i32, pnum: i32) -> Option<String> {
+        Tox::group_peername(self, gnum, pnum)
+    }
+
+    fn group_peer_pubkey(&self, gnum: i32, pnum: i32) -> Option<PublicKey> {
+        Tox::group_peer_pubkey(self, gnum, pnum)
+    }
+
+    fn group_number_peers(&self, gnum: i32) -> Option<i32> {
+        Tox::group_number_peers(self, gnum)
+    }
+
+    fn group_list(&self) -> Vec<i32> {
+        self.get_chatlist()
+    }
+
+    fn group_title(&self, gnum: i32) -> Option<String> {
+        self.group_get_title(gnum)
+    }
+
+    fn invite_friend(&mut self, fnum: u32, gnum: i32) -> Result<(), String> {
+        Tox::invite_friend(self, fnum as i32, gnum)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn join_group(&mut self, fnum: i32, data: &[u8]) -> Result<i32, String> {
+        self.join_groupchat(fnum, data).map_err(|e| format!("{:?}", e))
+    }
+
+    fn leave_group(&mut self, gnum: i32) -> Result<(), String> {
+        self.del_groupchat(gnum).map_err(|e| format!("{:?}", e))
+    }
+}
+
+
+
+/**
+    Something that Lee sent to Tox network through `FakeTransport`.
+*/
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sent {
+    FriendMessage(u32, String),
+    GroupMessage(i32, String),
+    StatusMessage(String),
+    FriendAdded(PublicKey),
+    Invite(u32, i32),
+    Joined(i32),
+    Left(i32),
+}
+
+
+/**
+    In-memory `Transport`, that doesn't talk to anyone, and instead records
+    everything that was sent through it, in order, in `sent`.
+
+    Friends and groupchat peers that Lee should know about have to be added
+    with `add_friend()` and `add_peer()`.
+*/
+#[cfg(test)]
+pub struct FakeTransport {
+    pub pk: PublicKey,
+    pub friends: HashMap<u32, PublicKey>,
+    /**
+        Peers in groupchats, as `(gnum, pnum) → (name, public key)`.
+    */
+    pub peers: HashMap<(i32, i32), (String, PublicKey)>,
+    pub titles: HashMap<i32, String>,
+    pub sent: Vec<Sent>,
+    next_group: i32,
+}
+
+#[cfg(test)]
+impl FakeTransport {
+    pub fn new(pk: PublicKey) -> FakeTransport {
+        FakeTransport {
+            pk: pk,
+            friends: HashMap::new(),
+            peers: HashMap::new(),
+            titles: HashMap::new(),
+            sent: vec![],
+            next_group: 0,
+        }
+    }
+
+    pub fn add_peer(&mut self, gnum: i32, pnum: i32, name: &str, pk: PublicKey) {
+        self.peers.insert((gnum, pnum), (name.to_string(), pk));
+    }
+
+    pub fn remove_peer(&mut self, gnum: i32, pnum: i32) {
+        self.peers.remove(&(gnum, pnum));
+    }
+
+    /**
+        Take everything that was recorded so far, leaving `sent` empty.
+    */
+    pub fn take_sent(&mut self) -> Vec<Sent> {
+        ::std::mem::replace(&mut self.sent, vec![])
+    }
+}
+
+#[cfg(test)]
+impl Transport for FakeTransport {
+    fn public_key(&self) -> PublicKey {
+        self.pk.clone()
+    }
+
+    fn address(&self) -> String {
+        format!("{}", self.pk)
+    }
+
+    fn set_status_message(&mut self, message: &str) -> Result<(), String> {
+        self.sent.push(Sent::StatusMessage(message.to_string()));
+        Ok(())
+    }
+
+    fn add_friend(&mut self, pk: &PublicKey) -> Result<u32, String> {
+        let fnum = self.friends.len() as u32;
+        self.friends.insert(fnum, pk.clone());
+        self.sent.push(Sent::FriendAdded(pk.clone()));
+        Ok(fnum)
+    }
+
+    fn friend_public_key(&self, fnum: u32) -> Option<PublicKey> {
+        self.friends.get(&fnum).cloned()
+    }
+
+    fn friend_count(&self) -> usize {
+        self.friends.len()
+    }
+
+    fn send_friend_message(&mut self, fnum: u32, message: &str)
+        -> Result<(), String>
+    {
+        if !self.friends.contains_key(&fnum) {
+            return Err(format!("no friend {}", fnum));
+        }
+        self.sent.push(Sent::FriendMessage(fnum, message.to_string()));
+        Ok(())
+    }
+
+    fn send_group_message(&mut self, gnum: i32, message: &str)
+        -> Result<(), String>
+    {
+        self.sent.push(Sent::GroupMessage(gnum, message.to_string()));
+        Ok(())
+    }
+
+    fn group_peername(&self, gnum: i32, pnum: i32) -> Option<String> {
+        self.peers.get(&(gnum, pnum)).map(|p| p.0.clone())
+    }
+
+    fn group_peer_pubkey(&self, gnum: i32, pnum: i32) -> Option<PublicKey> {
+        self.peers.get(&(gnum, pnum)).map(|p| p.1.clone())
+    }
+
+    fn group_number_peers(&self, gnum: i32) -> Option<i32> {
+        let peers = self.peers.keys().filter(|k| k.0 == gnum).count();
+        if peers == 0 {
+            None
+        } else {
+            Some(peers as i32)
+        }
+    }
+
+    fn group_list(&self) -> Vec<i32> {
+        let mut groups: Vec<i32> = self.peers.keys().map(|k| k.0).collect();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    fn group_title(&self, gnum: i32) -> Option<String> {
+        self.titles.get(&gnum).cloned()
+    }
+
+    fn invite_friend(&mut self, fnum: u32, gnum: i32) -> Result<(), String> {
+        if !self.friends.contains_key(&fnum) {
+            return Err(format!("no friend {}", fnum));
+        }
+        self.sent.push(Sent::Invite(fnum, gnum));
+        Ok(())
+    }
+
+    fn join_group(&mut self, _fnum: i32, _data: &[u8]) -> Result<i32, String> {
+        let gnum = self.next_group;
+        self.next_group += 1;
+        let pk = self.pk.clone();
+        self.add_peer(gnum, 0, "", pk);
+        self.sent.push(Sent::Joined(gnum));
+        Ok(gnum)
+    }
+
+    fn leave_group(&mut self, gnum: i32) -> Result<(), String> {
+        let left: Vec<(i32, i32)> = self.peers.keys()
+            .filter(|k| k.0 == gnum).cloned().collect();
+        for k in left {
+            self.peers.remove(&k);
+        }
+        self.titles.remove(&gnum);
+        self.sent.push(Sent::Left(gnum));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_joined_up_to_max_length() {
+        let line: String = ::std::iter::repeat('x').take(600).collect();
+        let lines = vec!["header".to_string(), line.clone(), line.clone(),
+                         line.clone()];
+        let messages = join_lines(&lines);
+        assert_eq!(messages, vec![format!("header\n{}\n{}", line, line),
+                                  line.clone()]);
+        assert!(messages.iter().all(|m| m.len() <= MAX_MESSAGE_LENGTH));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+    Copyright © 2015 Zetok Zalbavar <zetok@openmailbox.org>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+////////////////////////////////////////////////////////////////////////////
+
+/*
+    Deciding whether message in groupchat should trigger Lee's response.
+
+    Lee responds when its name or one of its aliases is mentioned as
+    a whole word – so that e.g. "sleek" wouldn't trigger Lee – or when
+    message matches one of configured regular expressions. Optionally,
+    it can also respond, with some chance, to questions that are asked to
+    everyone in groupchat, rather than to someone in particular.
+*/
+
+use regex::Regex;
+
+
+/**
+    Triggers configuration.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriggerConfig {
+    /**
+        Other names that Lee responds to, in addition to its own.
+    */
+    pub aliases: Vec<String>,
+
+    /**
+        Regular expressions that trigger Lee when they match message.
+    */
+    pub patterns: Vec<String>,
+
+    /**
+        Chance of responding to a question asked to everyone, in range
+        `0.0 – 1.0`.
+    */
+    pub question_chance: f64,
+}
+
+impl Default for TriggerConfig {
+    fn default() -> TriggerConfig {
+        TriggerConfig {
+            aliases: vec![],
+            patterns: vec![],
+            question_chance: 0.0,
+        }
+    }
+}
+
+
+pub struct Triggers {
+    pub config: TriggerConfig,
+
+    /**
+        Lowercase name and aliases.
+    */
+    names: Vec<String>,
+
+    patterns: Vec<Regex>,
+
+    /**
+        Matches start of a message addressed to someone, e.g. `nick: ` or
+        `@nick `. `nick, ` isn't there, since e.g. "So, what now?" is
+        asked to everyone – it's checked against names of peers instead.
+    */
+    addressed: Regex,
+}
+
+
+/*
+    Check whether `word` is in `text` as a whole word, i.e. it isn't
+    preceded nor followed by an alphanumeric character.
+*/
+fn contains_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    let mut start = 0;
+    while let Some(i) = text[start..].find(word) {
+        let begin = start + i;
+        let end = begin + word.len();
+        let before = text[..begin].chars().next_back();
+        let after = text[end..].chars().next();
+        if !before.map(|c| c.is_alphanumeric()).unwrap_or(false)
+            && !after.map(|c| c.is_alphanumeric()).unwrap_or(false) {
+            return true;
+        }
+        // move past first character of the match
+        start = begin + text[begin..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+    false
+}
+
+
+impl Triggers {
+    /**
+        Make triggers for Lee with given `name`, or return error if one of
+        patterns isn't a valid regular expression.
+    */
+    pub fn new(name: &str, config: TriggerConfig) -> Result<Triggers, String> {
+        let mut patterns = Vec::with_capacity(config.patterns.len());
+        for p in &config.patterns {
+            patterns.push(try!(Regex::new(p)
+                .map_err(|e| format!("invalid pattern {:?}: {}", p, e))));
+        }
+
+        let mut names = vec![name.to_lowercase()];
+        names.extend(config.aliases.iter().map(|a| a.to_lowercase()));
+
+        Ok(Triggers {
+            config: config,
+            names: names,
+            patterns: patterns,
+            addressed: Regex::new(r"^\s*(@\S+|[^\s:]+:)\s").unwrap(),
+        })
+    }
+
+    /**
+        Lowercase name and aliases.
+    */
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /**
+        Check whether Lee is mentioned by name or alias, or whether message
+        matches one of patterns.
+    */
+    pub fn is_mentioned(&self, msg: &str) -> bool {
+        let lowercase = msg.to_lowercase();
+        self.names.iter().any(|n| contains_word(&lowercase, n))
+            || self.patterns.iter().any(|p| p.is_match(msg))
+    }
+
+    /**
+        Check whether message is a question that isn't addressed to anyone
+        in particular, with `peers` being names of peers in groupchat.
+    */
+    pub fn is_room_question(&self, msg: &str, peers: &[String]) -> bool {
+        if !msg.trim_right().ends_with('?') || self.addressed.is_match(msg) {
+            return false;
+        }
+        let lowercase = msg.trim_left().to_lowercase();
+        !peers.iter().any(|p| {
+            let p = p.to_lowercase();
+            !p.is_empty() && lowercase.starts_with(&format!("{},", p))
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggers(aliases: &[&str], patterns: &[&str]) -> Triggers {
+        Triggers::new("Lee", TriggerConfig {
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            question_chance: 1.0,
+        }).unwrap()
+    }
+
+    #[test]
+    fn words_are_matched_whole() {
+        assert!(contains_word("lee", "lee"));
+        assert!(contains_word("hi lee!", "lee"));
+        assert!(contains_word("lee's chain", "lee"));
+        assert!(contains_word("(lee)", "lee"));
+        assert!(contains_word("sleek leek, lee", "lee"));
+        assert!(contains_word("ćma lee", "lee"));
+
+        assert!(!contains_word("sleek", "lee"));
+        assert!(!contains_word("fleet", "lee"));
+        assert!(!contains_word("leet", "lee"));
+        assert!(!contains_word("żlee", "lee"));
+        assert!(!contains_word("", "lee"));
+        assert!(!contains_word("lee", ""));
+    }
+
+    #[test]
+    fn name_aliases_and_patterns_trigger() {
+        let t = triggers(&["Bot Lee", "Ли"], &[r"(?i)\bbots?\b"]);
+        assert!(t.is_mentioned("LEE, hi"));
+        assert!(t.is_mentioned("is bot lee here"));
+        assert!(t.is_mentioned("привет, ли"));
+        assert!(t.is_mentioned("any Bots here"));
+
+        assert!(!t.is_mentioned("a sleek fleet"));
+        assert!(!t.is_mentioned("robots"));
+        assert!(!t.is_mentioned("лиса"));
+        assert!(Triggers::new("Lee", TriggerConfig {
+            patterns: vec!["(".to_string()],
+            ..TriggerConfig::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn questions_to_everyone_are_recognized() {
+        let t = triggers(&[], &[]);
+        let peers = vec!["Alice".to_string(), "Tox User".to_string()];
+        for msg in &["anyone here?", "So, what now?", "Well, anyone?",
+                     "what is 2 + 2 ?  ", "Alice? anyone?"] {
+            assert!(t.is_room_question(msg, &peers), "{:?}", msg);
+        }
+        for msg in &["Alice, you there?", "alice, you there?",
+                     " tox user, hello?", "Bob: you there?", "@Bob you there?",
+                     "time: anyone knows?",
+                     "not a question", "what? no"] {
+            assert!(!t.is_room_question(msg, &peers), "{:?}", msg);
+        }
+    }
+}